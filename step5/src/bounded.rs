@@ -0,0 +1,38 @@
+// src/bounded.rs
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Whether `key` may get an entry in `map` without the map growing past `max` entries. Once it
+/// is full, the entries `expired` picks out are cleared to make room. For maps filled by
+/// whatever arrives over the network, so a flood from spoofed sources can't exhaust memory.
+pub(crate) fn make_room<K, V, F>(map: &mut HashMap<K, V>, key: &K, max: usize, expired: F) -> bool
+where
+    K: Eq + Hash,
+    F: Fn(&V) -> bool,
+{
+    if map.len() < max || map.contains_key(key) {
+        return true;
+    }
+    map.retain(|_, value| !expired(value));
+    map.len() < max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_map_makes_room_only_by_expiring() {
+        let mut map: HashMap<u32, bool> = [(1, false), (2, true)].into_iter().collect();
+        let expired = |&stale: &bool| stale;
+
+        // Existing keys can always be updated, new ones push out the expired entries
+        assert!(make_room(&mut map, &1, 2, expired));
+        assert!(make_room(&mut map, &3, 2, expired));
+        assert_eq!(map.len(), 1);
+
+        map.insert(3, false);
+        assert!(!make_room(&mut map, &4, 2, expired));
+        assert_eq!(map.len(), 2);
+    }
+}
@@ -0,0 +1,368 @@
+// src/cache.rs
+use std::collections::{HashMap, HashSet};
+use std::sync::{Condvar, Mutex};
+use std::time::Instant;
+
+use crate::bounded;
+use crate::dns::{
+    Class, ErrorCondition, Message, Question, Type, RCODE_NAME_ERROR, RCODE_NO_ERROR,
+};
+
+/// Questions remembered at most, once full only entries that expired make room for new ones
+const MAX_ENTRIES: usize = 10_000;
+
+// The canonical form of the name, so names differing in case share an entry
+type Key = (Vec<u8>, Type, Class);
+
+/// Forwarded responses, kept for as long as the shortest TTL among their records allows, or
+/// for negative answers what their SOA record allows (RFC 2308). Answers taken from the cache
+/// have their TTLs lowered by the time they spent in it, so a client never holds on to a record
+/// longer than the upstream intended.
+#[derive(Debug, Default)]
+pub struct Cache {
+    state: Mutex<State>,
+    // Signalled whenever a forward finishes, for queries waiting on the same question
+    forwarded: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<Key, Entry>,
+    in_flight: HashSet<Key>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: Message,
+    stored: Instant,
+    ttl: u32,
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Cache::default()
+    }
+
+    /// The cached response to `question`, with TTLs counted down, if it hasn't expired yet
+    pub fn get(&self, question: &Question) -> Option<Message> {
+        self.state.lock().unwrap().fresh(&key(question))
+    }
+
+    /// Answer `question` from the cache, or ask `resolver` and cache what it returns. While one
+    /// caller forwards a question, others asking the same wait for its answer instead of sending
+    /// their own. Only NOERROR and NXDOMAIN answers are cached, SERVFAIL and other failures go
+    /// back to the caller and the next query tries again.
+    ///
+    /// The ID of the returned message is the one of the forwarded query, a server answering a
+    /// client has to put the client's ID in.
+    pub fn get_or_forward<F>(
+        &self,
+        question: &Question,
+        resolver: F,
+    ) -> Result<Message, ErrorCondition>
+    where
+        F: FnOnce(&Message) -> Result<Message, ErrorCondition>,
+    {
+        let key = key(question);
+
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(response) = state.fresh(&key) {
+                return Ok(response);
+            }
+            if !state.in_flight.contains(&key) {
+                break;
+            }
+            state = self.forwarded.wait(state).unwrap();
+        }
+        state.in_flight.insert(key.clone());
+        drop(state);
+
+        let forwarding = Forwarding { cache: self, key };
+        let result = resolver(&Message::query_for(question.clone()));
+        if let Ok(response) = &result {
+            let key = forwarding.key.clone();
+            self.state.lock().unwrap().insert(key, response);
+        }
+        result
+    }
+}
+
+// Marks a question as being forwarded. Dropping it wakes up the waiting queries, also when the
+// resolver panics, so they don't wait for an answer that never comes.
+struct Forwarding<'a> {
+    cache: &'a Cache,
+    key: Key,
+}
+
+impl Drop for Forwarding<'_> {
+    fn drop(&mut self) {
+        let mut state = self.cache.state.lock().unwrap();
+        state.in_flight.remove(&self.key);
+        self.cache.forwarded.notify_all();
+    }
+}
+
+impl State {
+    fn fresh(&mut self, key: &Key) -> Option<Message> {
+        let entry = self.entries.get(key)?;
+        let age = entry.stored.elapsed().as_secs().min(u32::MAX as u64) as u32;
+        if age >= entry.ttl {
+            self.entries.remove(key);
+            return None;
+        }
+
+        let mut response = entry.response.clone();
+        let sections = [
+            &mut response.answers,
+            &mut response.authorities,
+            &mut response.additionals,
+        ];
+        for record in sections.into_iter().flatten() {
+            record.ttl -= age;
+        }
+        Some(response)
+    }
+
+    fn insert(&mut self, key: Key, response: &Message) {
+        let Some(ttl) = cache_ttl(response) else {
+            return;
+        };
+
+        let expired = |entry: &Entry| entry.stored.elapsed().as_secs() >= entry.ttl as u64;
+        if !bounded::make_room(&mut self.entries, &key, MAX_ENTRIES, expired) {
+            return;
+        }
+
+        self.entries.insert(
+            key,
+            Entry {
+                response: response.clone(),
+                stored: Instant::now(),
+                ttl,
+            },
+        );
+    }
+}
+
+// How long `response` may be cached, None if not at all. A negative answer, NXDOMAIN or NOERROR
+// without answers, lives no longer than the TTL and the MINIMUM field of the SOA record in its
+// authority section. Without an SOA there is nothing telling how long the name stays missing,
+// so it isn't cached (RFC 2308 section 5).
+fn cache_ttl(response: &Message) -> Option<u32> {
+    let rcode = response.header.rcode;
+    if response.header.tc || !matches!(rcode, RCODE_NO_ERROR | RCODE_NAME_ERROR) {
+        return None;
+    }
+
+    // Every record is counted down while cached, none of them may outlive the entry
+    let shortest = response
+        .answers
+        .iter()
+        .chain(&response.authorities)
+        .chain(&response.additionals)
+        .map(|record| record.ttl)
+        .min()?;
+    if rcode == RCODE_NO_ERROR && !response.answers.is_empty() {
+        return Some(shortest);
+    }
+
+    let soa = response
+        .authorities
+        .iter()
+        .find(|record| record.rtype == Type::SOA)?;
+    // MINIMUM is the last of the five numbers after the two names
+    let minimum = u32::from_be_bytes(*soa.rdata.last_chunk::<4>()?);
+    Some(shortest.min(minimum))
+}
+
+fn key(question: &Question) -> Key {
+    (
+        question.name.to_canonical_bytes(),
+        question.qtype.clone(),
+        question.qclass.clone(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Name, ResourceRecord, RCODE_SERVER_FAILURE};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn question(name: &str) -> Question {
+        Question::new(Name::parse(name).unwrap(), Type::A, Class::IN).unwrap()
+    }
+
+    // A resolver answering every query with one A record, counting how often it is asked
+    fn upstream(
+        forwards: &Cell<u32>,
+    ) -> impl FnOnce(&Message) -> Result<Message, ErrorCondition> + '_ {
+        |query| {
+            forwards.set(forwards.get() + 1);
+            let mut response = query.response();
+            let name = query.questions[0].name.clone();
+            response.answers = vec![ResourceRecord::new(
+                name,
+                Type::A,
+                Class::IN,
+                300,
+                vec![10, 0, 0, 1],
+            )?];
+            Ok(response)
+        }
+    }
+
+    #[test]
+    fn test_miss_populates_and_hit_does_not_forward() {
+        let cache = Cache::new();
+        let forwards = Cell::new(0);
+
+        assert!(cache.get(&question("www.example.com")).is_none());
+        let missed = cache
+            .get_or_forward(&question("www.example.com"), upstream(&forwards))
+            .unwrap();
+        assert_eq!(forwards.get(), 1);
+        assert!(cache.get(&question("www.example.com")).is_some());
+
+        let hit = cache
+            .get_or_forward(&question("WWW.Example.com"), upstream(&forwards))
+            .unwrap();
+        assert_eq!(forwards.get(), 1);
+        assert_eq!(hit.answers, missed.answers);
+    }
+
+    #[test]
+    fn test_servfail_is_not_cached() {
+        let cache = Cache::new();
+        let servfail = |query: &Message| {
+            let mut response = query.response();
+            response.header.rcode = RCODE_SERVER_FAILURE;
+            Ok(response)
+        };
+
+        let response = cache
+            .get_or_forward(&question("www.example.com"), servfail)
+            .unwrap();
+        assert_eq!(response.header.rcode, RCODE_SERVER_FAILURE);
+        assert!(cache.get(&question("www.example.com")).is_none());
+
+        // Neither is a forward that failed outright
+        let failed = cache.get_or_forward(&question("www.example.com"), |_| {
+            Err(ErrorCondition::DeadlineExceeded)
+        });
+        assert!(failed.is_err());
+        assert!(cache.get(&question("www.example.com")).is_none());
+    }
+
+    // Pretend the entry for `name` was stored `secs` seconds earlier than it was
+    fn age(cache: &Cache, name: &str, secs: u64) {
+        let mut state = cache.state.lock().unwrap();
+        let entry = state.entries.get_mut(&key(&question(name))).unwrap();
+        entry.stored -= Duration::from_secs(secs);
+    }
+
+    #[test]
+    fn test_ttl_counts_down_until_expired() {
+        let cache = Cache::new();
+        let forwards = Cell::new(0);
+        cache
+            .get_or_forward(&question("www.example.com"), upstream(&forwards))
+            .unwrap();
+
+        age(&cache, "www.example.com", 120);
+        let response = cache.get(&question("www.example.com")).unwrap();
+        assert_eq!(response.answers[0].ttl, 180);
+
+        age(&cache, "www.example.com", 180);
+        assert!(cache.get(&question("www.example.com")).is_none());
+        cache
+            .get_or_forward(&question("www.example.com"), upstream(&forwards))
+            .unwrap();
+        assert_eq!(forwards.get(), 2);
+    }
+
+    // A negative answer with an SOA record of TTL 3600 and MINIMUM `minimum`
+    fn negative(
+        rcode: u8,
+        minimum: u32,
+    ) -> impl FnOnce(&Message) -> Result<Message, ErrorCondition> {
+        move |query| {
+            let mut response = query.response();
+            response.header.rcode = rcode;
+            let mut rdata = Name::parse("ns1.example.com")?.to_bytes()?;
+            rdata.extend(Name::parse("hostmaster.example.com")?.to_bytes()?);
+            for field in [1, 7200, 900, 1209600, minimum] {
+                rdata.extend(u32::to_be_bytes(field));
+            }
+            let soa = ResourceRecord::new(
+                Name::parse("example.com")?,
+                Type::SOA,
+                Class::IN,
+                3600,
+                rdata,
+            )?;
+            response.authorities.push(soa);
+            Ok(response)
+        }
+    }
+
+    #[test]
+    fn test_negative_answers_expire_with_soa_minimum() {
+        let cache = Cache::new();
+        for (name, rcode) in [
+            ("missing.example.com", RCODE_NAME_ERROR),
+            ("nodata.example.com", RCODE_NO_ERROR),
+        ] {
+            cache
+                .get_or_forward(&question(name), negative(rcode, 300))
+                .unwrap();
+
+            age(&cache, name, 299);
+            let response = cache.get(&question(name)).unwrap();
+            assert_eq!(response.header.rcode, rcode);
+            assert_eq!(response.authorities[0].ttl, 3301);
+
+            age(&cache, name, 1);
+            assert!(cache.get(&question(name)).is_none(), "{}", name);
+        }
+
+        // Without an SOA record it is not known how long the name stays missing
+        let nxdomain = |query: &Message| {
+            let mut response = query.response();
+            response.header.rcode = RCODE_NAME_ERROR;
+            Ok(response)
+        };
+        cache
+            .get_or_forward(&question("other.example.com"), nxdomain)
+            .unwrap();
+        assert!(cache.get(&question("other.example.com")).is_none());
+    }
+
+    #[test]
+    fn test_concurrent_misses_forward_once() {
+        let cache = Arc::new(Cache::new());
+        let forwards = Arc::new(AtomicU32::new(0));
+
+        let clients: Vec<_> = (0..8)
+            .map(|_| {
+                let (cache, forwards) = (cache.clone(), forwards.clone());
+                std::thread::spawn(move || {
+                    cache.get_or_forward(&question("www.example.com"), |query| {
+                        forwards.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(100));
+                        upstream(&Cell::new(0))(query)
+                    })
+                })
+            })
+            .collect();
+        for client in clients {
+            assert_eq!(client.join().unwrap().unwrap().answers.len(), 1);
+        }
+
+        assert_eq!(forwards.load(Ordering::SeqCst), 1);
+    }
+}
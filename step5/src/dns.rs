@@ -1,6 +1,11 @@
 // src/dns.rs
+use std::collections::HashMap;
+
 use thiserror::Error;
 
+use crate::edns::Edns;
+use crate::rng::{Rng, XorShift};
+
 #[derive(Debug, Error)]
 pub enum ErrorCondition {
     #[error("Serialization Error: {0}")]
@@ -11,12 +16,34 @@ pub enum ErrorCondition {
 
     #[error("Invalid Label")]
     InvalidLabel,
+
+    #[error("Name is {0} bytes long, at most 255 are allowed")]
+    NameTooLong(usize),
+
+    #[error("Zone Error on line {line}: {reason}")]
+    ZoneErr { line: usize, reason: String },
+
+    #[error("Timeout: {0}")]
+    TimeoutErr(String),
+
+    #[error("Deadline exceeded")]
+    DeadlineExceeded,
+
+    #[error("IO Error: {0}")]
+    IoErr(#[from] std::io::Error),
 }
 
 /// Maximum DNS message size without EDNS0
-const MAX_DNS_MESSAGE_SIZE: usize = 512;
+pub const MAX_DNS_MESSAGE_SIZE: usize = 512;
 
-#[derive(Debug)]
+// Response codes (RCODE) as defined in RFC 1035 section 4.1.1
+pub const RCODE_NO_ERROR: u8 = 0;
+pub const RCODE_SERVER_FAILURE: u8 = 2;
+pub const RCODE_NAME_ERROR: u8 = 3;
+pub const RCODE_NOT_IMPLEMENTED: u8 = 4;
+pub const RCODE_REFUSED: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Header {
     pub id: u16,      // identifier
     pub qr: bool,     // 0 for query, 1 for response
@@ -34,7 +61,7 @@ pub struct Header {
 }
 
 impl Header {
-    const DNS_HEADER_LEN: usize = 12;
+    pub const DNS_HEADER_LEN: usize = 12;
 
     // Serialize the header to a byte array
     pub fn to_bytes(&self) -> Vec<u8> {
@@ -83,34 +110,279 @@ impl Header {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Question {
-    pub name: Vec<Label>,
+    pub name: Name,
     pub qtype: Type,
     pub qclass: Class,
 }
 
-#[derive(Debug, Clone)]
-pub struct Label(String);
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Label(Vec<u8>);
+
+/// Maximum length of a single label (RFC 1035 section 2.3.4)
+pub const MAX_LABEL_LEN: usize = 63;
+
+/// Maximum length of an encoded name including the length octets (RFC 1035 section 2.3.4)
+pub const MAX_NAME_LEN: usize = 255;
 
 impl Label {
     pub fn new(label: &[u8]) -> Result<Self, ErrorCondition> {
-        match std::str::from_utf8(label) {
-            Ok(s) => Ok(Label(s.to_string())),
-            Err(_) => Err(ErrorCondition::InvalidLabel),
+        if label.is_empty() || label.len() > MAX_LABEL_LEN {
+            return Err(ErrorCondition::InvalidLabel);
         }
+
+        Ok(Label(label.to_vec()))
     }
 
     pub fn len(&self) -> usize {
         self.0.len()
     }
 
-    pub fn as_str(&self) -> &str {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Labels are arbitrary octets on the wire, they need not be ASCII or even UTF-8
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.0 == b"*"
+    }
+}
+
+/// Renders the label for logs and messages. Printable ASCII is shown as is, every other
+/// octet as a `\xNN` escape, so a label that is not valid UTF-8 can always be displayed.
+/// Dots and backslashes inside a label are escaped too, keeping the dotted form unambiguous.
+impl std::fmt::Display for Label {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for &byte in &self.0 {
+            match byte {
+                0x21..=0x7E if byte != b'.' && byte != b'\\' => write!(f, "{}", byte as char)?,
+                _ => write!(f, "\\x{:02x}", byte)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A domain name as its sequence of labels, e.g. [www, rust-trends, com]. The root label at the
+/// end is implied, so the root name itself has no labels.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Name(Vec<Label>);
+
+impl Name {
+    pub fn new(labels: Vec<Label>) -> Result<Self, ErrorCondition> {
+        let name = Name(labels);
+        name.validate()?;
+        Ok(name)
+    }
+
+    // Parse a name in dotted notation, a trailing dot is allowed
+    pub fn parse(name: &str) -> Result<Self, ErrorCondition> {
+        let name = name.strip_suffix('.').unwrap_or(name);
+        if name.is_empty() {
+            return Ok(Name::default());
+        }
+
+        name.split('.')
+            .map(|label| match label {
+                "" => Err(ErrorCondition::InvalidLabel),
+                label => Label::new(label.as_bytes()),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(Name::new)
+    }
+
+    // Reads a name at `start` of a full message, following compression pointers. Returns the
+    // name and the offset right after it in the original position. The name is checked against
+    // the 255 octet limit while it is read, so crafted pointers can't make it grow any larger.
+    pub fn from_message(buf: &[u8], start: usize) -> Result<(Self, usize), ErrorCondition> {
+        let mut labels = Vec::new();
+        let mut encoded_len = 1; // the root label
+        let mut index = start;
+        let mut jumped = false;
+        let mut jump_position = 0;
+
+        loop {
+            let len = read_slice(buf, index, 1)?[0] as usize;
+            if len == 0 {
+                index += 1;
+                break;
+            }
+
+            if len & 0b11000000 == 0b11000000 {
+                if !jumped {
+                    jump_position = index + 2;
+                }
+                // A pointer refers to a prior occurrence of the name, one that doesn't point
+                // backwards could be part of a loop
+                let target = pointer_target(buf, index)?;
+                if target >= index {
+                    return Err(ErrorCondition::DeserializationErr(format!(
+                        "Compression pointer at {} points forward to {}",
+                        index, target
+                    )));
+                }
+                index = target;
+                jumped = true;
+                continue;
+            }
+
+            encoded_len += 1 + len;
+            if encoded_len > MAX_NAME_LEN {
+                return Err(ErrorCondition::NameTooLong(encoded_len));
+            }
+
+            index += 1;
+            labels.push(Label::new(read_slice(buf, index, len)?)?);
+            index += len;
+        }
+
+        if !jumped {
+            jump_position = index;
+        }
+
+        Ok((Name::new(labels)?, jump_position))
+    }
+
+    // Reads a name stored in RDATA. Compression pointers in RDATA point into the message the
+    // record came from, so that message is needed to follow them.
+    pub fn from_rdata(rdata: &[u8], packet: &[u8]) -> Result<Self, ErrorCondition> {
+        let mut labels = Vec::new();
+        let mut index = 0;
+
+        loop {
+            let len = read_slice(rdata, index, 1)?[0] as usize;
+            if len == 0 {
+                break;
+            }
+
+            if len & 0b11000000 == 0b11000000 {
+                let (rest, _) = Name::from_message(packet, pointer_target(rdata, index)?)?;
+                labels.extend(rest.0);
+                break;
+            }
+
+            index += 1;
+            labels.push(Label::new(read_slice(rdata, index, len)?)?);
+            index += len;
+        }
+
+        Name::new(labels)
+    }
+
+    // Write the name in uncompressed wire format: length-prefixed labels ending with 0
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ErrorCondition> {
+        self.validate()?;
+
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        for label in &self.0 {
+            let len = u8::try_from(label.len()).map_err(|_| {
+                ErrorCondition::SerializationErr(format!("Label too long: {}", label))
+            })?;
+            buf.push(len);
+            buf.extend_from_slice(&label.0);
+        }
+        buf.push(0);
+
+        Ok(buf)
+    }
+
+    /// The name in canonical form (RFC 4034 section 6.2): uncompressed wire format with ASCII
+    /// letters lowercased. Names that are equal ignoring case have the same canonical form,
+    /// which makes it the key zones and the cache store names under. Unlike the dotted form it
+    /// keeps every octet as it is, labels don't have to be ASCII.
+    pub fn to_canonical_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        for label in &self.0 {
+            buf.push(label.len() as u8);
+            buf.extend(label.0.iter().map(u8::to_ascii_lowercase));
+        }
+        buf.push(0);
+        buf
+    }
+
+    // Length in wire format, one length octet per label plus the terminating root label
+    pub fn encoded_len(&self) -> usize {
+        self.0.iter().map(|label| 1 + label.len()).sum::<usize>() + 1
+    }
+
+    pub fn validate(&self) -> Result<(), ErrorCondition> {
+        if self.encoded_len() > MAX_NAME_LEN {
+            return Err(ErrorCondition::NameTooLong(self.encoded_len()));
+        }
+        Ok(())
+    }
+
+    pub fn labels(&self) -> &[Label] {
         &self.0
     }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.0.first().is_some_and(|label| label.is_wildcard())
+    }
+
+    // The name without its leftmost label, None for the root
+    pub fn parent(&self) -> Option<Name> {
+        (!self.is_root()).then(|| Name(self.0[1..].to_vec()))
+    }
+
+    // The wildcard name `*.<self>` that would cover children of this name
+    pub fn wildcard_child(&self) -> Name {
+        let mut labels = vec![Label(b"*".to_vec())];
+        labels.extend_from_slice(&self.0);
+        Name(labels)
+    }
+
+    // Names compare case-insensitively, see RFC 4343
+    pub fn ends_with(&self, suffix: &Name) -> bool {
+        self.0.len() >= suffix.0.len()
+            && self.0[self.0.len() - suffix.0.len()..]
+                .iter()
+                .zip(&suffix.0)
+                .all(|(a, b)| a.0.eq_ignore_ascii_case(&b.0))
+    }
+
+    pub fn eq_ignore_case(&self, other: &Name) -> bool {
+        self.0.len() == other.0.len() && self.ends_with(other)
+    }
+
+    /// The owner name of an answer synthesized from the wildcard record `wildcard` for the name
+    /// `queried` (RFC 4592). The `*` stands for one or more whole labels on the left, so exactly
+    /// those labels of the queried name replace it and the rest is kept from the wildcard.
+    /// If `wildcard` is not a wildcard that covers `queried` it is returned unchanged.
+    pub fn substitute_wildcard(wildcard: &Name, queried: &Name) -> Name {
+        let suffix = Name(wildcard.0.iter().skip(1).cloned().collect());
+        if !wildcard.is_wildcard()
+            || queried.0.len() <= suffix.0.len()
+            || !queried.ends_with(&suffix)
+        {
+            return wildcard.clone();
+        }
+
+        let mut labels = queried.0[..queried.0.len() - suffix.0.len()].to_vec();
+        labels.extend(suffix.0);
+        Name(labels)
+    }
+}
+
+impl std::fmt::Display for Name {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let labels: Vec<String> = self.0.iter().map(|l| l.to_string()).collect();
+        write!(f, "{}", labels.join("."))
+    }
 }
 
-#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[repr(u16)]
 pub enum Type {
     // Below are Resource Record Types and QTYPES
     A = 1,      // a host address
@@ -129,15 +401,23 @@ pub enum Type {
     MINFO = 14, // mailbox or mail list information
     MX = 15,    // mail exchange
     TXT = 16,   // text strings
+    AAAA = 28,  // an IPv6 host address (RFC 3596)
+    SRV = 33,   // location of a service (RFC 2782)
+    OPT = 41,   // EDNS pseudo-record, only in the additional section (RFC 6891)
 
     // Below are only QTYPES
     AXFR = 252,  // A request for a transfer of an entire zone
     MAILB = 253, // A request for mailbox-related records (MB, MG or MR)
     MAILA = 254, // A request for mail agent RRs (Obsolete - see MX)
     _ALL_ = 255, // A request for all records
+
+    // Any other type, like HTTPS or RRSIG, carried along with its code (RFC 3597)
+    Unknown(u16),
 }
 
-#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[repr(u16)]
 pub enum Class {
     // Below are Resource Record Classes and QCLASS
     IN = 1, // the Internet
@@ -147,11 +427,15 @@ pub enum Class {
 
     // Below are only QCLASSES
     _ALL_ = 255,
+
+    // Any other class, like NONE (254) from dynamic updates, carried along with its code
+    Other(u16),
 }
 
 impl std::fmt::Display for Type {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let msg: &str = match self {
+            Type::Unknown(code) => return write!(f, "an unknown type (TYPE{})", code),
             Type::A => "a host address",
             Type::NS => "an authoritative name server",
             Type::MD => "a mail destination (Obsolete - use MX)",
@@ -168,6 +452,9 @@ impl std::fmt::Display for Type {
             Type::MINFO => "mailbox or mail list information",
             Type::MX => "mail exchange",
             Type::TXT => "text strings",
+            Type::AAAA => "an IPv6 host address",
+            Type::SRV => "location of a service",
+            Type::OPT => "EDNS options",
             Type::AXFR => "A request for a transfer of an entire zone",
             Type::MAILB => "A request for mailbox-related records (MB, MG or MR)",
             Type::MAILA => "A request for mail agent RRs (Obsolete - see MX)",
@@ -197,13 +484,14 @@ impl Type {
             14 => Ok(Type::MINFO),
             15 => Ok(Type::MX),
             16 => Ok(Type::TXT),
+            28 => Ok(Type::AAAA),
+            33 => Ok(Type::SRV),
+            41 => Ok(Type::OPT),
             252 => Ok(Type::AXFR),
             253 => Ok(Type::MAILB),
             254 => Ok(Type::MAILA),
             255 => Ok(Type::_ALL_),
-            n => Err(ErrorCondition::DeserializationErr(
-                format!("Unknown Question Type {}", n).to_string(),
-            )),
+            n => Ok(Type::Unknown(n)),
         }
     }
 
@@ -225,10 +513,14 @@ impl Type {
             Type::MINFO => 14,
             Type::MX => 15,
             Type::TXT => 16,
+            Type::AAAA => 28,
+            Type::SRV => 33,
+            Type::OPT => 41,
             Type::AXFR => 252,
             Type::MAILB => 253,
             Type::MAILA => 254,
             Type::_ALL_ => 255,
+            Type::Unknown(n) => *n,
         };
 
         u16::to_be_bytes(num)
@@ -243,9 +535,8 @@ impl Class {
             2 => Ok(Class::CS),
             3 => Ok(Class::CH),
             4 => Ok(Class::HS),
-            _ => Err(ErrorCondition::DeserializationErr(
-                format!("Unknown Question Class {}", num).to_string(),
-            )),
+            255 => Ok(Class::_ALL_),
+            n => Ok(Class::Other(n)),
         }
     }
 
@@ -256,100 +547,123 @@ impl Class {
             Class::CH => 3,
             Class::HS => 4,
             Class::_ALL_ => 255,
+            Class::Other(n) => *n,
         };
 
         u16::to_be_bytes(num)
     }
 }
 
+impl std::str::FromStr for Class {
+    type Err = String;
+
+    // The mnemonics used in zone files and by dig, e.g. IN or CH
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "IN" => Ok(Class::IN),
+            "CS" => Ok(Class::CS),
+            "CH" => Ok(Class::CH),
+            "HS" => Ok(Class::HS),
+            _ => Err(format!("unknown class '{}', expected IN, CS, CH or HS", s)),
+        }
+    }
+}
+
 impl Question {
     // The from_bytes() function reconstructs a Question struct by iterating through the buffer, extracting labels,
-    // parsing the query type and class.
+    // parsing the query type and class. `buf` starts right after the header, an empty or truncated
+    // question is an error rather than a reason to index past the end.
     pub fn from_bytes(buf: &[u8]) -> Result<Self, ErrorCondition> {
+        if buf.is_empty() {
+            return Err(ErrorCondition::DeserializationErr(
+                "Empty question section".to_string(),
+            ));
+        }
+
         let mut index = 0;
         let mut labels: Vec<Label> = Vec::new();
 
         println!("Labels:");
-        while buf[index] != 0 {
-            let len = buf[index] as usize;
+        loop {
+            let len = read_slice(buf, index, 1)?[0] as usize;
             index += 1;
-            labels.push(Label::new(&buf[index..index + len])?);
-            println!("{:?}", labels); // For debugging purposes
+            if len == 0 {
+                break;
+            }
+            labels.push(Label::new(read_slice(buf, index, len)?)?);
+            println!("{}", labels[labels.len() - 1]); // For debugging purposes
             index += len;
         }
 
-        index += 1;
-
-        let qtype = Type::from_bytes(&buf[index..index + 2])?;
+        let qtype = Type::from_bytes(read_slice(buf, index, 2)?)?;
         index += 2;
-        let qclass = Class::from_bytes(&buf[index..index + 2])?;
+        let qclass = Class::from_bytes(read_slice(buf, index, 2)?)?;
 
         Ok(Question {
-            name: labels,
+            name: Name::new(labels)?,
             qtype,
             qclass,
         })
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
+    pub fn new(name: Name, qtype: Type, qclass: Class) -> Result<Self, ErrorCondition> {
+        name.validate()?;
+        Ok(Question {
+            name,
+            qtype,
+            qclass,
+        })
+    }
 
-        // Write the labels to the buffer and add . inbetween and end with 0
-        for label in &self.name {
-            buf.push(label.len() as u8);
-            buf.extend_from_slice(label.0.as_bytes());
-        }
-        buf.push(0);
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ErrorCondition> {
+        // Write the labels to the buffer and end with 0
+        let mut buf = self.name.to_bytes()?;
 
         // Write the question type and class to the buffer
         buf.extend_from_slice(&self.qtype.to_bytes());
         buf.extend_from_slice(&self.qclass.to_bytes());
 
-        buf
+        Ok(buf)
     }
 
-    pub fn decompress_name(buf: &[u8], start: usize) -> Result<(String, usize), ErrorCondition> {
-        let mut name = String::new();
-        let mut index = start;
-        let mut jumped = false;
-        let mut jump_position = 0;
-
-        loop {
-            let len = buf[index] as usize;
-            if len == 0 {
-                index += 1;
-                break;
-            }
+    // Append the question to a message in `buf`, compressing the name with `names`
+    pub fn write(&self, names: &mut NameWriter, buf: &mut Vec<u8>) -> Result<(), ErrorCondition> {
+        names.write_name(&self.name, buf)?;
+        buf.extend_from_slice(&self.qtype.to_bytes());
+        buf.extend_from_slice(&self.qclass.to_bytes());
+        Ok(())
+    }
 
-            if len & 0b11000000 == 0b11000000 {
-                if !jumped {
-                    jump_position = index + 2;
-                }
-                let offset = ((len & 0b00111111) as usize) << 8 | buf[index + 1] as usize;
-                index = offset;
-                jumped = true;
-                continue;
-            }
+    pub fn decompress_name(buf: &[u8], start: usize) -> Result<(String, usize), ErrorCondition> {
+        let (name, next) = Name::from_message(buf, start)?;
+        Ok((name.to_string(), next))
+    }
 
-            index += 1;
-            if !name.is_empty() {
-                name.push('.');
-            }
-            name.push_str(&String::from_utf8_lossy(&buf[index..index + len]));
-            index += len;
-        }
+    // Reads a question at `offset` of a full message, following compression pointers in the name
+    pub fn from_message(buf: &[u8], offset: usize) -> Result<(Self, usize), ErrorCondition> {
+        let (name, index) = Name::from_message(buf, offset)?;
+        let qtype = Type::from_bytes(read_slice(buf, index, 2)?)?;
+        let qclass = Class::from_bytes(read_slice(buf, index + 2, 2)?)?;
 
-        if !jumped {
-            jump_position = index;
-        }
+        Ok((
+            Question {
+                name,
+                qtype,
+                qclass,
+            },
+            index + 4,
+        ))
+    }
 
-        Ok((name, jump_position))
+    // The question name in dotted notation, e.g. www.rust-trends.com
+    pub fn name_to_string(&self) -> String {
+        self.name.to_string()
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ResourceRecord {
-    pub name: String,
+    pub name: Name,
     pub rtype: Type,
     pub rclass: Class,
     pub ttl: u32,
@@ -360,7 +674,7 @@ pub struct ResourceRecord {
 impl Default for ResourceRecord {
     fn default() -> Self {
         ResourceRecord {
-            name: String::from("www.rust-trends.com"),
+            name: Name::parse("www.rust-trends.com").unwrap(),
             rtype: Type::A,
             rclass: Class::IN,
             ttl: 60,
@@ -371,50 +685,446 @@ impl Default for ResourceRecord {
 }
 
 impl ResourceRecord {
-    pub fn to_bytes(&self) -> Vec<u8> {
+    pub fn new(
+        name: Name,
+        rtype: Type,
+        rclass: Class,
+        ttl: u32,
+        rdata: Vec<u8>,
+    ) -> Result<Self, ErrorCondition> {
+        name.validate()?;
+        let rdlength = u16::try_from(rdata.len()).map_err(|_| {
+            ErrorCondition::SerializationErr(format!("RDATA of {} bytes is too long", rdata.len()))
+        })?;
+
+        Ok(ResourceRecord {
+            name,
+            rtype,
+            rclass,
+            ttl,
+            rdlength,
+            rdata,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ErrorCondition> {
         let mut buf = Vec::with_capacity(MAX_DNS_MESSAGE_SIZE);
 
-        self.name.split('.').for_each(|label| {
-            buf.push(label.len() as u8);
-            buf.extend_from_slice(label.as_bytes());
-        });
+        buf.extend_from_slice(&self.name.to_bytes()?);
+        self.write_fields(&mut buf);
 
-        buf.push(0);
+        Ok(buf)
+    }
+
+    /// The name this record points to, for record types whose RDATA holds a name: the alias of a
+    /// CNAME, the server of an NS, the exchange of an MX (after its preference), the primary
+    /// name server of an SOA, the target of an SRV (after priority, weight and port), and the
+    /// names in PTR, MB, MG and MR records. `packet` is the message the record was read from,
+    /// used to follow compression pointers. Returns None for other types or malformed RDATA.
+    pub fn target_name(&self, packet: &[u8]) -> Option<Name> {
+        let skip = match self.rtype {
+            Type::CNAME | Type::NS | Type::PTR | Type::MB | Type::MG | Type::MR | Type::SOA => 0,
+            Type::MX => 2,
+            Type::SRV => 6,
+            _ => return None,
+        };
+
+        Name::from_rdata(self.rdata.get(skip..)?, packet).ok()
+    }
+
+    // Append the record to a message in `buf`, compressing the owner name with `names`
+    pub fn write(&self, names: &mut NameWriter, buf: &mut Vec<u8>) -> Result<(), ErrorCondition> {
+        names.write_name(&self.name, buf)?;
+        self.write_fields(buf);
+        Ok(())
+    }
+
+    /// Append an RRset, records sharing owner name, type and class, to a message in `buf`. The
+    /// owner name is written once, every following record gets a pointer to it without looking
+    /// it up in `names` again. Fails without writing anything when a record doesn't belong to
+    /// the set of the first one.
+    pub fn write_rrset(
+        records: &[ResourceRecord],
+        names: &mut NameWriter,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), ErrorCondition> {
+        let Some(first) = records.first() else {
+            return Ok(());
+        };
+        if let Some(other) = records.iter().find(|record| !record.same_rrset(first)) {
+            return Err(ErrorCondition::SerializationErr(format!(
+                "{} {:?} {:?} is not part of the RRset of {} {:?} {:?}",
+                other.name, other.rclass, other.rtype, first.name, first.rclass, first.rtype
+            )));
+        }
+
+        let start = buf.len();
+        names.write_name(&first.name, buf)?;
+        // Reuse the owner as written, unless a pointer to it is shorter
+        let pointable = names.compress && start <= NameWriter::MAX_POINTER_OFFSET;
+        let owner = if pointable && buf.len() - start > 2 {
+            (0xC000 | start as u16).to_be_bytes().to_vec()
+        } else {
+            buf[start..].to_vec()
+        };
+        first.write_fields(buf);
+
+        for record in &records[1..] {
+            buf.extend_from_slice(&owner);
+            record.write_fields(buf);
+        }
+        Ok(())
+    }
+
+    // Owner names compare case-insensitively, like everywhere else in DNS
+    fn same_rrset(&self, other: &ResourceRecord) -> bool {
+        self.name.eq_ignore_case(&other.name)
+            && self.rtype == other.rtype
+            && self.rclass == other.rclass
+    }
+
+    // Everything after the owner name: type, class, TTL, RDLENGTH and RDATA
+    fn write_fields(&self, buf: &mut Vec<u8>) {
         buf.extend_from_slice(&self.rtype.to_bytes());
         buf.extend_from_slice(&self.rclass.to_bytes());
         buf.extend_from_slice(&self.ttl.to_be_bytes());
         buf.extend_from_slice(&self.rdlength.to_be_bytes());
         buf.extend_from_slice(&self.rdata);
-
-        buf
     }
 
     pub fn from_bytes(buf: &[u8], offset: usize) -> Result<(Self, usize), ErrorCondition> {
-        let (name, mut index) = Question::decompress_name(buf, offset)?;
+        let (name, mut index) = Name::from_message(buf, offset)?;
 
-        let rtype = Type::from_bytes(&buf[index..index + 2])?;
+        let rtype = Type::from_bytes(read_slice(buf, index, 2)?)?;
         index += 2;
-        let rclass = Class::from_bytes(&buf[index..index + 2])?;
+        let rclass = Class::from_bytes(read_slice(buf, index, 2)?)?;
         index += 2;
-        let ttl = u32::from_be_bytes(buf[index..index + 4].try_into().unwrap());
+        let ttl = u32::from_be_bytes(read_slice(buf, index, 4)?.try_into().unwrap());
         index += 4;
-        let rdlength = u16::from_be_bytes(buf[index..index + 2].try_into().unwrap()) as usize;
+        let rdlength = u16::from_be_bytes(read_slice(buf, index, 2)?.try_into().unwrap()) as usize;
         index += 2;
-        let rdata = buf[index..index + rdlength].to_vec();
+        let rdata = decompress_rdata(&rtype, buf, index, rdlength)?;
         index += rdlength;
 
-        Ok((
-            ResourceRecord {
-                name,
-                rtype,
-                rclass,
-                ttl,
-                rdlength: rdlength as u16,
-                rdata,
+        let record = ResourceRecord::new(name, rtype, rclass, ttl, rdata)?;
+        Ok((record, index))
+    }
+}
+
+// The RDATA of a record at `start` in `packet`. Names in the RDATA of the types from RFC 1035
+// may be compressed (RFC 3597 section 4), with pointers that only make sense in the message the
+// record came from. They are spelled out, so the record can be written into another message.
+fn decompress_rdata(
+    rtype: &Type,
+    packet: &[u8],
+    start: usize,
+    len: usize,
+) -> Result<Vec<u8>, ErrorCondition> {
+    // Octets before the first name and the number of names following them
+    let (prefix, names) = match rtype {
+        Type::NS | Type::MD | Type::MF | Type::CNAME => (0, 1),
+        Type::MB | Type::MG | Type::MR | Type::PTR => (0, 1),
+        Type::SOA | Type::MINFO => (0, 2),
+        Type::MX => (2, 1),
+        _ => return Ok(read_slice(packet, start, len)?.to_vec()),
+    };
+
+    let end = start + len;
+    let mut rdata = read_slice(packet, start, prefix)?.to_vec();
+    let mut index = start + prefix;
+    for _ in 0..names {
+        let (name, next) = Name::from_message(packet, index)?;
+        rdata.extend(name.to_bytes()?);
+        index = next;
+    }
+    if index > end {
+        return Err(ErrorCondition::DeserializationErr(format!(
+            "{:?} RDATA runs past its length of {}",
+            rtype, len
+        )));
+    }
+    rdata.extend_from_slice(read_slice(packet, index, end - index)?);
+    Ok(rdata)
+}
+
+/// Writes names into a message using compression (RFC 1035 section 4.1.4). The offset of every
+/// name suffix written so far is remembered, and a later name ending in the same labels gets a
+/// pointer to the earlier occurrence instead of spelling those labels out again.
+#[derive(Debug)]
+pub struct NameWriter {
+    offsets: HashMap<Vec<Label>, u16>,
+    compress: bool,
+}
+
+impl Default for NameWriter {
+    fn default() -> Self {
+        NameWriter {
+            offsets: HashMap::new(),
+            compress: true,
+        }
+    }
+}
+
+impl NameWriter {
+    /// Pointers hold 14 bits of offset, names written further into the message can't be targets
+    const MAX_POINTER_OFFSET: usize = 0x3FFF;
+
+    pub fn new() -> Self {
+        NameWriter::default()
+    }
+
+    /// A writer that spells out every name in full, for comparing against the compressed form
+    /// or for clients that can't handle pointers
+    pub fn uncompressed() -> Self {
+        NameWriter {
+            offsets: HashMap::new(),
+            compress: false,
+        }
+    }
+
+    // Append `name` to `buf`, which must contain the message being written from its first byte
+    pub fn write_name(&mut self, name: &Name, buf: &mut Vec<u8>) -> Result<(), ErrorCondition> {
+        name.validate()?;
+
+        let labels = name.labels();
+        for i in 0..labels.len() {
+            if let Some(&offset) = self.offsets.get(&labels[i..]) {
+                buf.extend_from_slice(&(0xC000 | offset).to_be_bytes());
+                return Ok(());
+            }
+
+            if self.compress && buf.len() <= NameWriter::MAX_POINTER_OFFSET {
+                self.offsets.insert(labels[i..].to_vec(), buf.len() as u16);
+            }
+
+            let label = &labels[i];
+            let len = u8::try_from(label.len()).map_err(|_| {
+                ErrorCondition::SerializationErr(format!("Label too long: {}", label))
+            })?;
+            buf.push(len);
+            buf.extend_from_slice(&label.0);
+        }
+        buf.push(0);
+
+        Ok(())
+    }
+}
+
+/// A DNS message: the header followed by the question, answer, authority and additional
+/// sections. The EDNS OPT record is taken out of the additional section into `edns`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub header: Header,
+    pub questions: Vec<Question>,
+    pub answers: Vec<ResourceRecord>,
+    pub authorities: Vec<ResourceRecord>,
+    pub additionals: Vec<ResourceRecord>,
+    pub edns: Option<Edns>,
+}
+
+impl Message {
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, ErrorCondition> {
+        let header = Header::from_bytes(buf)?;
+        let mut offset = Header::DNS_HEADER_LEN;
+
+        let mut questions = Vec::new();
+        for _ in 0..header.qdcount {
+            let (question, next_offset) = Question::from_message(buf, offset)?;
+            questions.push(question);
+            offset = next_offset;
+        }
+
+        let mut answers = Vec::new();
+        for _ in 0..header.ancount {
+            let (record, next_offset) = ResourceRecord::from_bytes(buf, offset)?;
+            answers.push(record);
+            offset = next_offset;
+        }
+
+        let mut authorities = Vec::new();
+        for _ in 0..header.nscount {
+            let (record, next_offset) = ResourceRecord::from_bytes(buf, offset)?;
+            authorities.push(record);
+            offset = next_offset;
+        }
+
+        let mut additionals = Vec::new();
+        let mut edns = None;
+        for _ in 0..header.arcount {
+            let (_, index) = Name::from_message(buf, offset)?;
+            let fields = read_slice(buf, index, 10)?;
+            let rdlength = u16::from_be_bytes([fields[8], fields[9]]) as usize;
+            // The OPT record's class and TTL fields hold EDNS parameters, not a class and a TTL
+            if fields[..2] == Type::OPT.to_bytes() {
+                edns = Some(Edns::from_bytes(read_slice(buf, index + 2, 8 + rdlength)?)?);
+                offset = index + 10 + rdlength;
+            } else {
+                let (record, next_offset) = ResourceRecord::from_bytes(buf, offset)?;
+                additionals.push(record);
+                offset = next_offset;
+            }
+        }
+
+        Ok(Message {
+            header,
+            questions,
+            answers,
+            authorities,
+            additionals,
+            edns,
+        })
+    }
+
+    // Serialize the message, the section counts in the header are taken from the sections themselves
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ErrorCondition> {
+        self.write(NameWriter::new())
+    }
+
+    /// Serialize the message without name compression, every name is written out label by
+    /// label. Larger than `to_bytes`, but parses back to the same message, which makes it handy
+    /// to see what compression does on the wire.
+    pub fn to_bytes_uncompressed(&self) -> Result<Vec<u8>, ErrorCondition> {
+        self.write(NameWriter::uncompressed())
+    }
+
+    fn write(&self, mut names: NameWriter) -> Result<Vec<u8>, ErrorCondition> {
+        let header = Header {
+            qdcount: self.questions.len() as u16,
+            ancount: self.answers.len() as u16,
+            nscount: self.authorities.len() as u16,
+            arcount: (self.additionals.len() + self.edns.is_some() as usize) as u16,
+            ..self.header.clone()
+        };
+
+        // Names are compressed against everything written before them, so the writer sees the
+        // whole message from the first header byte on
+        let mut buf = header.to_bytes();
+        for question in &self.questions {
+            question.write(&mut names, &mut buf)?;
+        }
+        for section in [&self.answers, &self.authorities, &self.additionals] {
+            for rrset in section.chunk_by(|a, b| a.same_rrset(b)) {
+                ResourceRecord::write_rrset(rrset, &mut names, &mut buf)?;
+            }
+        }
+        if let Some(edns) = &self.edns {
+            edns.write(&mut buf)?;
+        }
+
+        Ok(buf)
+    }
+
+    /// Serialize the message for a transport that carries at most `max_size` bytes, like UDP.
+    /// Additional records are left out first, the answer is complete without them. If that is
+    /// not enough, answers, then authority records, are dropped from the end until the message
+    /// fits and the TC bit tells the client to ask again over TCP (RFC 2181 section 9). The
+    /// question section is always kept.
+    pub fn to_bytes_truncated(&self, max_size: usize) -> Result<Vec<u8>, ErrorCondition> {
+        let buf = self.to_bytes()?;
+        if buf.len() <= max_size {
+            return Ok(buf);
+        }
+
+        let mut truncated = self.clone();
+        truncated.additionals.clear();
+        let buf = truncated.to_bytes()?;
+        if buf.len() <= max_size {
+            return Ok(buf);
+        }
+
+        truncated.header.tc = true;
+        while truncated.answers.pop().is_some() || truncated.authorities.pop().is_some() {
+            let buf = truncated.to_bytes()?;
+            if buf.len() <= max_size {
+                return Ok(buf);
+            }
+        }
+        truncated.to_bytes()
+    }
+
+    /// A standard query for `name` and `qtype` in class IN with a random ID and recursion
+    /// desired, ready to be sent. Fails when `name` is not a valid domain name.
+    pub fn query(name: &str, qtype: Type) -> Result<Message, ErrorCondition> {
+        let question = Question::new(Name::parse(name)?, qtype, Class::IN)?;
+        Ok(Message::query_for(question))
+    }
+
+    /// Like `query`, for a question that is already built
+    pub fn query_for(question: Question) -> Message {
+        Message {
+            header: Header {
+                id: random_id(),
+                qr: false,
+                opcode: 0,
+                aa: false,
+                tc: false,
+                rd: true,
+                ra: false,
+                z: 0,
+                rcode: RCODE_NO_ERROR,
+                qdcount: 1,
+                ancount: 0,
+                nscount: 0,
+                arcount: 0,
             },
-            index,
-        ))
+            questions: vec![question],
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
+        }
+    }
+
+    // Start a response to this query: same ID, opcode and questions, no answers yet
+    pub fn response(&self) -> Message {
+        Message {
+            header: Header {
+                id: self.header.id,
+                qr: true,
+                opcode: self.header.opcode,
+                aa: false,
+                tc: false,
+                rd: self.header.rd,
+                ra: false,
+                z: 0,
+                rcode: RCODE_NO_ERROR,
+                qdcount: self.questions.len() as u16,
+                ancount: 0,
+                nscount: 0,
+                arcount: 0,
+            },
+            questions: self.questions.clone(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
+        }
+    }
+}
+
+// The offset a compression pointer at `index` points to. The header holds no names, so a
+// pointer before the first question can only come from a broken or malicious message.
+fn pointer_target(buf: &[u8], index: usize) -> Result<usize, ErrorCondition> {
+    let pointer = read_slice(buf, index, 2)?;
+    let offset = ((pointer[0] & 0b0011_1111) as usize) << 8 | pointer[1] as usize;
+    if offset < Header::DNS_HEADER_LEN {
+        return Err(ErrorCondition::DeserializationErr(format!(
+            "Compression pointer to offset {} points into the header",
+            offset
+        )));
     }
+    Ok(offset)
+}
+
+// A transaction ID that is hard to guess
+fn random_id() -> u16 {
+    XorShift::from_entropy().next_u32() as u16
+}
+
+// Borrow `len` bytes at `start`, failing instead of panicking on truncated input
+fn read_slice(buf: &[u8], start: usize, len: usize) -> Result<&[u8], ErrorCondition> {
+    buf.get(start..start + len)
+        .ok_or_else(|| ErrorCondition::DeserializationErr("Unexpected end of buffer".to_string()))
 }
 
 #[cfg(test)]
@@ -425,6 +1135,7 @@ mod tests {
     fn test_decompress_name_with_pointer() {
         // Two-question packet: first has uncompressed www.rust-trends.com,
         // second has dev.rust-trends.com using a pointer to offset 0x10
+        #[rustfmt::skip]
         let packet: Vec<u8> = vec![
             // Header (12 bytes)
             0x43, 0xE6, 0x01, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
@@ -449,4 +1160,511 @@ mod tests {
         assert_eq!(name2, "dev.rust-trends.com");
         assert_eq!(next2, 43);
     }
+
+    #[test]
+    fn test_truncated_question_is_rejected() {
+        let question = [3, b'w', b'w', b'w', 0, 0, 1, 0, 1];
+        assert!(Question::from_bytes(&question).is_ok());
+
+        for len in 0..question.len() {
+            assert!(
+                matches!(
+                    Question::from_bytes(&question[..len]),
+                    Err(ErrorCondition::DeserializationErr(_))
+                ),
+                "{} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn test_header_counts_beyond_message_are_rejected() {
+        // A bare header claiming the maximum of every section
+        let buf = [
+            0x43, 0xE6, 0x81, 0x80, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+        assert!(matches!(
+            Message::from_bytes(&buf),
+            Err(ErrorCondition::DeserializationErr(_))
+        ));
+    }
+
+    // A message with one answer owned by the name in `owner`, given in wire format
+    fn record_with_owner(owner: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0x43, 0xE6, 0x81, 0x80, 0, 0, 0, 1, 0, 0, 0, 0];
+        buf.extend_from_slice(owner);
+        buf.extend_from_slice(&[0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 1]);
+        buf
+    }
+
+    fn labels(sizes: &[usize]) -> Vec<u8> {
+        let mut owner = Vec::new();
+        for &size in sizes {
+            owner.push(size as u8);
+            owner.extend(std::iter::repeat_n(b'a', size));
+        }
+        owner.push(0);
+        owner
+    }
+
+    #[test]
+    fn test_owner_name_length_is_bounded() {
+        // 3 * 64 + 62 + 1 = 255 octets, as long as a name may be
+        let buf = record_with_owner(&labels(&[63, 63, 63, 61]));
+        let (record, _) = ResourceRecord::from_bytes(&buf, 12).unwrap();
+        assert_eq!(record.name.encoded_len(), MAX_NAME_LEN);
+        assert!(Message::from_bytes(&buf).is_ok());
+
+        let buf = record_with_owner(&labels(&[63, 63, 63, 62]));
+        assert!(matches!(
+            ResourceRecord::from_bytes(&buf, 12),
+            Err(ErrorCondition::NameTooLong(256))
+        ));
+        assert!(Message::from_bytes(&buf).is_err());
+
+        // A pointer back to its own label would repeat it forever without the bound
+        let buf = record_with_owner(&[1, b'a', 0xC0, 12]);
+        assert!(matches!(
+            ResourceRecord::from_bytes(&buf, 12),
+            Err(ErrorCondition::NameTooLong(_))
+        ));
+    }
+
+    #[test]
+    fn test_pointer_must_point_backwards() {
+        // Pointing at itself, and at the label after it
+        for owner in [[0xC0, 12, 0, 0], [0xC0, 14, 1, b'a']] {
+            let buf = record_with_owner(&owner);
+            assert!(matches!(
+                ResourceRecord::from_bytes(&buf, 12),
+                Err(ErrorCondition::DeserializationErr(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_pointer_into_header_is_rejected() {
+        // Header, then a question name that is only a pointer
+        let mut packet = vec![0x43, 0xE6, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        packet.extend_from_slice(&[0xC0, 4, 0, 1, 0, 1]);
+        assert!(matches!(
+            Name::from_message(&packet, 12),
+            Err(ErrorCondition::DeserializationErr(_))
+        ));
+        assert!(Message::from_bytes(&packet).is_err());
+        assert!(Name::from_rdata(&[0xC0, 4], &packet).is_err());
+
+        // Offset 12 is where the first question starts, pointing there is fine
+        let mut packet = vec![0x43, 0xE6, 0x01, 0x00, 0, 2, 0, 0, 0, 0, 0, 0];
+        packet.extend_from_slice(&[3, b'c', b'o', b'm', 0, 0, 1, 0, 1]);
+        packet.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1]);
+        let (name, next) = Name::from_message(&packet, 21).unwrap();
+        assert_eq!(name.to_string(), "com");
+        assert_eq!(next, 23);
+    }
+
+    #[test]
+    fn test_label_length_limits() {
+        assert!(matches!(
+            Label::new(&[b'a'; 64]),
+            Err(ErrorCondition::InvalidLabel)
+        ));
+        assert!(matches!(Label::new(b""), Err(ErrorCondition::InvalidLabel)));
+        assert_eq!(Label::new(&[b'a'; 63]).unwrap().len(), 63);
+    }
+
+    #[test]
+    fn test_non_utf8_label_is_displayed_escaped() {
+        // www.<0xff 'a' 0xc3>.com, the middle label is not valid UTF-8
+        let packet = [
+            3, b'w', b'w', b'w', 3, 0xFF, b'a', 0xC3, 3, b'c', b'o', b'm', 0,
+        ];
+        let (name, next) = Name::from_message(&packet, 0).unwrap();
+        assert_eq!(next, packet.len());
+        assert_eq!(name.labels()[1].as_bytes(), &[0xFF, b'a', 0xC3]);
+        assert_eq!(name.to_string(), "www.\\xffa\\xc3.com");
+
+        let question = Question::new(name, Type::A, Class::IN).unwrap();
+        assert_eq!(question.name_to_string(), "www.\\xffa\\xc3.com");
+        assert_eq!(question.to_bytes().unwrap()[..packet.len()], packet);
+
+        let dotted = Label::new(b"a.b\\").unwrap();
+        assert_eq!(dotted.to_string(), "a\\x2eb\\x5c");
+    }
+
+    #[test]
+    fn test_canonical_form_lowercases_ascii_only() {
+        let name = Name::parse("WWW.Café.com").unwrap();
+        let mut expected = vec![3, b'w', b'w', b'w', 5, b'c', b'a', b'f', 0xC3, 0xA9];
+        expected.extend([3, b'c', b'o', b'm', 0]);
+        assert_eq!(name.to_canonical_bytes(), expected);
+        assert_eq!(Name::default().to_canonical_bytes(), [0]);
+    }
+
+    #[test]
+    fn test_name_length_limit() {
+        let label = Label::new(&[b'a'; 63]).unwrap();
+
+        // 3 * (1 + 63) + (1 + 61) + 1 = 255 bytes is just allowed
+        let mut labels = vec![label.clone(); 3];
+        labels.push(Label::new(&[b'b'; 61]).unwrap());
+        let name = Name::new(labels.clone()).unwrap();
+        assert_eq!(name.encoded_len(), 255);
+        assert_eq!(name.to_bytes().unwrap().len(), 255);
+
+        labels.push(Label::new(b"c").unwrap());
+        assert!(matches!(
+            Name::new(labels),
+            Err(ErrorCondition::NameTooLong(257))
+        ));
+
+        // A wildcard child of a name at the limit is too long to be sent
+        let question = Question::new(name.wildcard_child(), Type::A, Class::IN);
+        assert!(matches!(question, Err(ErrorCondition::NameTooLong(257))));
+    }
+
+    #[test]
+    fn test_compressed_message_round_trip() {
+        let name = |n: &str| Name::parse(n).unwrap();
+        let record = |n: &str, ip: [u8; 4]| {
+            ResourceRecord::new(name(n), Type::A, Class::IN, 60, ip.to_vec()).unwrap()
+        };
+
+        let query = Message::query("www.example.com", Type::A).unwrap();
+        let mut response = query.response();
+        response.answers = vec![
+            record("www.example.com", [10, 0, 0, 1]),
+            record("mail.example.com", [10, 0, 0, 2]),
+            record("example.com", [10, 0, 0, 3]),
+        ];
+
+        let bytes = response.to_bytes().unwrap();
+
+        // The question name is written in full at offset 12, the first answer points right back
+        // to it and the others only spell out their own first label, if any
+        assert_eq!(&bytes[33..35], &[0xC0, 12]);
+        assert_eq!(&bytes[49..56], &[4, b'm', b'a', b'i', b'l', 0xC0, 16]);
+        assert_eq!(&bytes[70..72], &[0xC0, 16]);
+        let uncompressed: usize = 12
+            + response.questions[0].to_bytes().unwrap().len()
+            + response
+                .answers
+                .iter()
+                .map(|a| a.to_bytes().unwrap().len())
+                .sum::<usize>();
+        assert_eq!(bytes.len(), uncompressed - 15 - 11 - 11);
+
+        let parsed = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.questions[0].name, response.questions[0].name);
+        assert_eq!(parsed.answers.len(), 3);
+        for (parsed, original) in parsed.answers.iter().zip(&response.answers) {
+            assert_eq!(parsed.name, original.name);
+            assert_eq!(parsed.rdata, original.rdata);
+        }
+    }
+
+    #[test]
+    fn test_uncompressed_message_parses_the_same() {
+        let name = |n: &str| Name::parse(n).unwrap();
+        let mut response = Message::query("www.example.com", Type::A)
+            .unwrap()
+            .response();
+        response.answers = vec![ResourceRecord::new(
+            name("www.example.com"),
+            Type::A,
+            Class::IN,
+            60,
+            vec![10, 0, 0, 1],
+        )
+        .unwrap()];
+        response.authorities = vec![ResourceRecord::new(
+            name("example.com"),
+            Type::NS,
+            Class::IN,
+            3600,
+            name("ns1.example.com").to_bytes().unwrap(),
+        )
+        .unwrap()];
+        response.edns = Some(Edns::new(1232));
+
+        let compressed = response.to_bytes().unwrap();
+        let uncompressed = response.to_bytes_uncompressed().unwrap();
+
+        // Without pointers every name is written in full
+        let mut expected = response.header.to_bytes();
+        expected[4..12].copy_from_slice(&[0, 1, 0, 1, 0, 1, 0, 1]);
+        expected.extend(response.questions[0].to_bytes().unwrap());
+        expected.extend(response.answers[0].to_bytes().unwrap());
+        expected.extend(response.authorities[0].to_bytes().unwrap());
+        response
+            .edns
+            .as_ref()
+            .unwrap()
+            .write(&mut expected)
+            .unwrap();
+        assert_eq!(uncompressed, expected);
+        assert!(compressed.len() < uncompressed.len());
+
+        let parsed = Message::from_bytes(&compressed).unwrap();
+        assert_eq!(parsed, Message::from_bytes(&uncompressed).unwrap());
+        assert_eq!(parsed.answers, response.answers);
+        assert_eq!(parsed.authorities, response.authorities);
+    }
+
+    #[test]
+    fn test_rrset_owner_written_once() {
+        let name = Name::parse("www.example.com").unwrap();
+        let records: Vec<_> = (1..=3)
+            .map(|i| {
+                ResourceRecord::new(name.clone(), Type::A, Class::IN, 60, vec![10, 0, 0, i])
+                    .unwrap()
+            })
+            .collect();
+
+        // Pretend a header came first, so the owner lands at offset 12
+        let mut buf = vec![0; 12];
+        let mut names = NameWriter::new();
+        ResourceRecord::write_rrset(&records, &mut names, &mut buf).unwrap();
+
+        let full = records[0].to_bytes().unwrap().len();
+        assert_eq!(&buf[12..full + 12], records[0].to_bytes().unwrap());
+        let second = 12 + full;
+        assert_eq!(&buf[second..second + 2], &[0xC0, 12]);
+        let third = second + 2 + 14;
+        assert_eq!(&buf[third..third + 2], &[0xC0, 12]);
+        assert_eq!(buf.len(), third + 2 + 14);
+
+        // Each record still reads back on its own
+        let (record, next) = ResourceRecord::from_bytes(&buf, second).unwrap();
+        assert_eq!(record, records[1]);
+        assert_eq!(
+            ResourceRecord::from_bytes(&buf, next).unwrap().0,
+            records[2]
+        );
+    }
+
+    #[test]
+    fn test_rrset_must_share_name_type_and_class() {
+        let name = Name::parse("www.example.com").unwrap();
+        let a = ResourceRecord::new(name.clone(), Type::A, Class::IN, 60, vec![10, 0, 0, 1]);
+        let aaaa = ResourceRecord::new(name, Type::AAAA, Class::IN, 60, vec![0; 16]);
+
+        let mut buf = Vec::new();
+        let result = ResourceRecord::write_rrset(
+            &[a.unwrap(), aaaa.unwrap()],
+            &mut NameWriter::new(),
+            &mut buf,
+        );
+        assert!(matches!(result, Err(ErrorCondition::SerializationErr(_))));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_types_and_additional_records_round_trip() {
+        let name = |n: &str| Name::parse(n).unwrap();
+        let mut response = Message::query("example.com", Type::Unknown(65))
+            .unwrap()
+            .response();
+        response.answers.push(
+            ResourceRecord::new(
+                name("example.com"),
+                Type::Unknown(65),
+                Class::IN,
+                300,
+                vec![0, 1, 0],
+            )
+            .unwrap(),
+        );
+        response.authorities.push(
+            ResourceRecord::new(
+                name("example.com"),
+                Type::NS,
+                Class::IN,
+                300,
+                name("ns1.example.com").to_bytes().unwrap(),
+            )
+            .unwrap(),
+        );
+        response.additionals.push(
+            ResourceRecord::new(
+                name("ns1.example.com"),
+                Type::A,
+                Class::IN,
+                300,
+                vec![10, 0, 0, 53],
+            )
+            .unwrap(),
+        );
+        response.edns = Some(Edns::new(1232));
+
+        let bytes = response.to_bytes().unwrap();
+        assert_eq!(&bytes[10..12], &[0, 2]);
+        let parsed = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.questions[0].qtype, Type::Unknown(65));
+        assert_eq!(parsed.answers, response.answers);
+        assert_eq!(parsed.additionals, response.additionals);
+        assert_eq!(parsed.edns, response.edns);
+        assert_eq!(parsed.to_bytes().unwrap(), bytes);
+
+        // Additional records are the first to go, without that counting as truncation
+        let without_glue = response.to_bytes_truncated(bytes.len() - 1).unwrap();
+        let parsed = Message::from_bytes(&without_glue).unwrap();
+        assert!(!parsed.header.tc);
+        assert!(parsed.additionals.is_empty());
+        assert_eq!(parsed.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_compressed_rdata_is_spelled_out() {
+        let name = |n: &str| Name::parse(n).unwrap();
+        // www.example.com sits at offset 12, example.com at 16
+        let mut packet = Message::query("www.example.com", Type::CNAME)
+            .unwrap()
+            .response()
+            .to_bytes()
+            .unwrap();
+        packet[7] = 2;
+        // www CNAME web.example.com, with web.<pointer to example.com>
+        packet.extend([0xC0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 6]);
+        packet.extend([3, b'w', b'e', b'b', 0xC0, 16]);
+        // example.com MX 10 mx.example.com, with mx.<pointer to example.com>
+        packet.extend([0xC0, 16, 0, 15, 0, 1, 0, 0, 0, 60, 0, 7]);
+        packet.extend([0, 10, 2, b'm', b'x', 0xC0, 16]);
+
+        let parsed = Message::from_bytes(&packet).unwrap();
+        let cname = &parsed.answers[0];
+        assert_eq!(cname.rdata, name("web.example.com").to_bytes().unwrap());
+        assert_eq!(cname.rdlength as usize, cname.rdata.len());
+        let mut mx = vec![0, 10];
+        mx.extend(name("mx.example.com").to_bytes().unwrap());
+        assert_eq!(parsed.answers[1].rdata, mx);
+
+        // Written into a message of its own, the records mean the same as before
+        let moved = Message::from_bytes(&parsed.to_bytes_uncompressed().unwrap()).unwrap();
+        assert_eq!(moved.answers, parsed.answers);
+
+        // A name running past the RDLENGTH is an error
+        let len = packet.len();
+        packet[len - 8] = 5;
+        assert!(Message::from_bytes(&packet).is_err());
+    }
+
+    #[test]
+    fn test_oversized_response_is_truncated() {
+        let name = Name::parse("www.example.com").unwrap();
+        let query = Message::query("www.example.com", Type::A).unwrap();
+        let mut response = query.response();
+        for i in 0..50 {
+            response.answers.push(
+                ResourceRecord::new(name.clone(), Type::A, Class::IN, 60, vec![10, 0, 0, i])
+                    .unwrap(),
+            );
+        }
+        assert!(response.to_bytes().unwrap().len() > MAX_DNS_MESSAGE_SIZE);
+
+        let bytes = response.to_bytes_truncated(MAX_DNS_MESSAGE_SIZE).unwrap();
+        assert!(bytes.len() <= MAX_DNS_MESSAGE_SIZE);
+
+        let parsed = Message::from_bytes(&bytes).unwrap();
+        assert!(parsed.header.tc);
+        assert_eq!(parsed.header.qdcount, 1);
+        assert_eq!(parsed.questions[0].name, name);
+        assert_eq!(parsed.header.ancount as usize, parsed.answers.len());
+        assert!(parsed.answers.len() < 50);
+
+        // A message too big for even its question still keeps it, with no answers
+        let bytes = response.to_bytes_truncated(12).unwrap();
+        let parsed = Message::from_bytes(&bytes).unwrap();
+        assert!(parsed.header.tc);
+        assert_eq!(parsed.questions.len(), 1);
+        assert!(parsed.answers.is_empty());
+
+        // Messages that fit are left alone
+        let bytes = query.to_bytes_truncated(MAX_DNS_MESSAGE_SIZE).unwrap();
+        assert!(!Message::from_bytes(&bytes).unwrap().header.tc);
+    }
+
+    #[test]
+    fn test_query_builder() {
+        let query = Message::query("www.example.com.", Type::AAAA).unwrap();
+        let parsed = Message::from_bytes(&query.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(parsed.header.qdcount, 1);
+        assert!(parsed.header.rd);
+        assert!(!parsed.header.qr);
+        assert_eq!(parsed.header.id, query.header.id);
+        assert_eq!(
+            parsed.questions[0].name,
+            Name::parse("www.example.com").unwrap()
+        );
+        assert_eq!(parsed.questions[0].qtype, Type::AAAA);
+        assert_eq!(parsed.questions[0].qclass, Class::IN);
+
+        assert!(matches!(
+            Message::query("www..example.com", Type::A),
+            Err(ErrorCondition::InvalidLabel)
+        ));
+        let long = format!("{}.com", vec!["a".repeat(63); 4].join("."));
+        assert!(matches!(
+            Message::query(&long, Type::A),
+            Err(ErrorCondition::NameTooLong(_))
+        ));
+    }
+
+    #[test]
+    fn test_target_name() {
+        let name = |n: &str| Name::parse(n).unwrap();
+        let query = Message::query("www.example.com", Type::A).unwrap();
+        // example.com sits at offset 16, right after the "www" label
+        let packet = query.to_bytes().unwrap();
+        let record = |rtype: Type, rdata: &[u8]| {
+            ResourceRecord::new(
+                name("www.example.com"),
+                rtype,
+                Class::IN,
+                60,
+                rdata.to_vec(),
+            )
+            .unwrap()
+        };
+
+        let cname = record(Type::CNAME, &[3, b'w', b'e', b'b', 0xC0, 16]);
+        assert_eq!(cname.target_name(&packet), Some(name("web.example.com")));
+
+        let mx = record(Type::MX, &[0, 10, 3, b'm', b'x', b'1', 0xC0, 16]);
+        assert_eq!(mx.target_name(&packet), Some(name("mx1.example.com")));
+
+        let srv = record(Type::SRV, &[0, 1, 0, 5, 0x13, 0xC4, 3, b's', b'i', b'p', 0]);
+        assert_eq!(srv.target_name(&[]), Some(name("sip")));
+
+        let a = record(Type::A, &[10, 0, 0, 1]);
+        assert_eq!(a.target_name(&packet), None);
+
+        let truncated = record(Type::MX, &[0]);
+        assert_eq!(truncated.target_name(&packet), None);
+    }
+
+    #[test]
+    fn test_substitute_wildcard() {
+        let wildcard = Name::parse("*.example.com").unwrap();
+
+        let owner = Name::substitute_wildcard(&wildcard, &Name::parse("foo.example.com").unwrap());
+        assert_eq!(owner, Name::parse("foo.example.com").unwrap());
+
+        // The * covers every label left of the wildcard's suffix, the suffix keeps its spelling
+        let owner = Name::substitute_wildcard(&wildcard, &Name::parse("a.b.EXAMPLE.com.").unwrap());
+        assert_eq!(owner.to_string(), "a.b.example.com");
+
+        // No substitution for the suffix itself, other domains or non-wildcards
+        for queried in ["example.com", "foo.example.org"] {
+            let owner = Name::substitute_wildcard(&wildcard, &Name::parse(queried).unwrap());
+            assert_eq!(owner, wildcard);
+        }
+        let plain = Name::parse("www.example.com").unwrap();
+        assert_eq!(
+            Name::substitute_wildcard(&plain, &Name::parse("foo.www.example.com").unwrap()),
+            plain
+        );
+    }
 }
@@ -0,0 +1,280 @@
+// src/edns.rs
+use crate::dns::{ErrorCondition, Message, Type, MAX_DNS_MESSAGE_SIZE};
+
+/// Largest UDP payload we offer to send and receive, the size recommended by DNS Flag Day 2020
+/// to stay clear of IP fragmentation
+pub const MAX_EDNS_PAYLOAD: u16 = 1232;
+
+/// An option carried in the RDATA of an OPT record (RFC 6891 section 6.1.2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EdnsOption {
+    /// Name server identifier (RFC 5001). Empty in a query that asks for it, holding the
+    /// server's identifier in the response.
+    Nsid(Vec<u8>),
+    /// Any option this server does not interpret. It is ignored in queries we answer ourselves,
+    /// and written back exactly as it was received, in its original place, when relayed.
+    Unknown { code: u16, data: Vec<u8> },
+}
+
+impl EdnsOption {
+    pub const NSID: u16 = 3;
+
+    pub fn code(&self) -> u16 {
+        match self {
+            EdnsOption::Nsid(_) => EdnsOption::NSID,
+            EdnsOption::Unknown { code, .. } => *code,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match self {
+            EdnsOption::Nsid(data) => data,
+            EdnsOption::Unknown { data, .. } => data,
+        }
+    }
+
+    fn new(code: u16, data: &[u8]) -> Self {
+        match code {
+            EdnsOption::NSID => EdnsOption::Nsid(data.to_vec()),
+            code => EdnsOption::Unknown {
+                code,
+                data: data.to_vec(),
+            },
+        }
+    }
+
+    // Parse the options in an OPT record's RDATA, each is a code, a length and that many bytes
+    fn parse_all(rdata: &[u8]) -> Result<Vec<EdnsOption>, ErrorCondition> {
+        let mut options = Vec::new();
+        let mut index = 0;
+
+        while index < rdata.len() {
+            let fields = rdata.get(index..index + 4).ok_or_else(truncated)?;
+            let code = u16::from_be_bytes([fields[0], fields[1]]);
+            let len = u16::from_be_bytes([fields[2], fields[3]]) as usize;
+            let data = rdata
+                .get(index + 4..index + 4 + len)
+                .ok_or_else(truncated)?;
+            options.push(EdnsOption::new(code, data));
+            index += 4 + len;
+        }
+
+        Ok(options)
+    }
+
+    fn write(&self, buf: &mut Vec<u8>) -> Result<(), ErrorCondition> {
+        let len = u16::try_from(self.data().len()).map_err(|_| {
+            ErrorCondition::SerializationErr(format!("EDNS option {} is too long", self.code()))
+        })?;
+        buf.extend_from_slice(&self.code().to_be_bytes());
+        buf.extend_from_slice(&len.to_be_bytes());
+        buf.extend_from_slice(self.data());
+        Ok(())
+    }
+}
+
+/// The contents of an OPT pseudo-record. Its fixed fields are repurposed: the class holds the
+/// largest UDP payload the sender can receive and the TTL holds the extended RCODE, the EDNS
+/// version and flags.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edns {
+    pub udp_payload_size: u16,
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+impl Edns {
+    pub fn new(udp_payload_size: u16) -> Self {
+        Edns {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+
+    // Parse the part of an OPT record after its owner name and type: class, TTL, RDLENGTH, RDATA
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, ErrorCondition> {
+        let fields = buf.get(..8).ok_or_else(truncated)?;
+        let rdlength = u16::from_be_bytes([fields[6], fields[7]]) as usize;
+        let rdata = buf.get(8..8 + rdlength).ok_or_else(truncated)?;
+
+        Ok(Edns {
+            udp_payload_size: u16::from_be_bytes([fields[0], fields[1]]),
+            extended_rcode: fields[2],
+            version: fields[3],
+            dnssec_ok: fields[4] & 0b1000_0000 != 0,
+            options: EdnsOption::parse_all(rdata)?,
+        })
+    }
+
+    // Append the whole OPT record, its owner name is always the root
+    pub fn write(&self, buf: &mut Vec<u8>) -> Result<(), ErrorCondition> {
+        let mut rdata = Vec::new();
+        for option in &self.options {
+            option.write(&mut rdata)?;
+        }
+        let rdlength = u16::try_from(rdata.len()).map_err(|_| {
+            ErrorCondition::SerializationErr("EDNS options are too long".to_string())
+        })?;
+
+        buf.push(0);
+        buf.extend_from_slice(&Type::OPT.to_bytes());
+        buf.extend_from_slice(&self.udp_payload_size.to_be_bytes());
+        buf.push(self.extended_rcode);
+        buf.push(self.version);
+        buf.push((self.dnssec_ok as u8) << 7);
+        buf.push(0);
+        buf.extend_from_slice(&rdlength.to_be_bytes());
+        buf.extend_from_slice(&rdata);
+        Ok(())
+    }
+
+    // True when an option with this code is present
+    pub fn has_option(&self, code: u16) -> bool {
+        self.options.iter().any(|option| option.code() == code)
+    }
+}
+
+/// What a query's OPT record tells about the client, kept while the query is processed. A
+/// query without one has no context, and its response must not have an OPT record either
+/// (RFC 6891 section 7).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdnsContext {
+    /// The largest UDP response the client can take
+    pub udp_payload_size: u16,
+    pub dnssec_ok: bool,
+    pub nsid_requested: bool,
+}
+
+impl EdnsContext {
+    pub fn from_query(query: &Message) -> Option<Self> {
+        let edns = query.edns.as_ref()?;
+        Some(EdnsContext {
+            udp_payload_size: edns.udp_payload_size,
+            dnssec_ok: edns.dnssec_ok,
+            nsid_requested: edns.has_option(EdnsOption::NSID),
+        })
+    }
+
+    /// How large a UDP response to this client may get: what it asked for, but never less than
+    /// the 512 bytes every client handles nor more than we are willing to send
+    pub fn max_response_size(&self) -> usize {
+        let size = self.udp_payload_size.min(MAX_EDNS_PAYLOAD) as usize;
+        size.max(MAX_DNS_MESSAGE_SIZE)
+    }
+
+    /// The OPT record for the response, with `nsid` only if the client asked for it
+    pub fn response_edns(&self, nsid: Option<&[u8]>) -> Edns {
+        let mut edns = Edns::new(MAX_EDNS_PAYLOAD);
+        if let (true, Some(nsid)) = (self.nsid_requested, nsid) {
+            edns.options.push(EdnsOption::Nsid(nsid.to_vec()));
+        }
+        edns
+    }
+}
+
+fn truncated() -> ErrorCondition {
+    ErrorCondition::DeserializationErr("Truncated OPT record".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Write `edns`, parse it back and check nothing changed, including the bytes when the
+    // result is written again
+    fn assert_round_trip(edns: &Edns) {
+        let mut buf = Vec::new();
+        edns.write(&mut buf).unwrap();
+
+        let parsed = Edns::from_bytes(&buf[3..]).unwrap();
+        assert_eq!(&parsed, edns);
+
+        let mut rewritten = Vec::new();
+        parsed.write(&mut rewritten).unwrap();
+        assert_eq!(rewritten, buf);
+    }
+
+    #[test]
+    fn test_option_mixes_round_trip_in_order() {
+        let unknown = |code, data: &[u8]| EdnsOption::Unknown {
+            code,
+            data: data.to_vec(),
+        };
+        let mixes = [
+            vec![],
+            vec![unknown(65001, b"relay me")],
+            vec![unknown(65001, &[]), EdnsOption::Nsid(Vec::new())],
+            vec![
+                unknown(10, &[0xAB; 8]),
+                EdnsOption::Nsid(b"ns1".to_vec()),
+                unknown(65001, &[0, 1, 2]),
+                unknown(10, &[0xCD; 8]),
+            ],
+        ];
+
+        for options in mixes {
+            let mut edns = Edns::new(4096);
+            edns.options = options;
+            assert_round_trip(&edns);
+        }
+    }
+
+    #[test]
+    fn test_unknown_option_parsed_from_wire() {
+        // Class 1232, TTL 0, RDLENGTH 6: option 65001 with two bytes of data
+        let wire = [0x04, 0xD0, 0, 0, 0, 0, 0, 6, 0xFD, 0xE9, 0, 2, 0xBE, 0xEF];
+        let edns = Edns::from_bytes(&wire).unwrap();
+        assert_eq!(
+            edns.options,
+            vec![EdnsOption::Unknown {
+                code: 65001,
+                data: vec![0xBE, 0xEF],
+            }]
+        );
+        assert!(!edns.has_option(EdnsOption::NSID));
+    }
+
+    #[test]
+    fn test_context_from_query() {
+        let mut query = Message::query("www.example.com", Type::A).unwrap();
+        assert_eq!(EdnsContext::from_query(&query), None);
+
+        let mut edns = Edns::new(4096);
+        edns.options.push(EdnsOption::Nsid(Vec::new()));
+        query.edns = Some(edns);
+        let context = EdnsContext::from_query(&query).unwrap();
+        assert!(context.nsid_requested);
+
+        // Capped at what we send, and no client gets less than plain DNS allows
+        assert_eq!(context.max_response_size(), MAX_EDNS_PAYLOAD as usize);
+        let small = EdnsContext {
+            udp_payload_size: 100,
+            ..context
+        };
+        assert_eq!(small.max_response_size(), MAX_DNS_MESSAGE_SIZE);
+    }
+
+    #[test]
+    fn test_opt_record_round_trip() {
+        let mut edns = Edns::new(1232);
+        edns.dnssec_ok = true;
+        edns.options.push(EdnsOption::Nsid(b"ns1".to_vec()));
+        edns.options.push(EdnsOption::Unknown {
+            code: 10,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        });
+
+        let mut buf = Vec::new();
+        edns.write(&mut buf).unwrap();
+        assert_eq!(&buf[..3], &[0, 0, 41]);
+
+        // Skip the root owner name and the type
+        assert_eq!(Edns::from_bytes(&buf[3..]).unwrap(), edns);
+        assert!(Edns::from_bytes(&buf[3..buf.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+// src/guard.rs
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::bounded;
+
+/// How long a source is ignored after it sent us a response
+const PENALTY_DURATION: Duration = Duration::from_secs(60);
+
+/// Sources remembered at most, so a flood from spoofed addresses can't exhaust memory
+const MAX_PENALIZED: usize = 10_000;
+
+/// What to do with a packet that has the QR bit set. A server never answers a response, but
+/// a stream of them usually means somebody spoofs our address to bounce traffic off us, or
+/// tries to get us to reflect traffic to a victim. Responses are always dropped, the policy
+/// decides what else happens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QrPolicy {
+    /// Drop the packet without a trace
+    Drop,
+    /// Drop the packet and log where it came from
+    #[default]
+    Log,
+    /// Log the source and stop answering it for a while, see [`PenaltyList`]
+    Penalize,
+}
+
+impl FromStr for QrPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(QrPolicy::Drop),
+            "log" => Ok(QrPolicy::Log),
+            "penalize" => Ok(QrPolicy::Penalize),
+            _ => Err(format!(
+                "unknown QR policy '{}', expected drop, log or penalize",
+                s
+            )),
+        }
+    }
+}
+
+/// Sources that misbehaved recently. Nothing is sent to them until their penalty expires,
+/// every new offense starts the penalty over.
+#[derive(Debug, Default)]
+pub struct PenaltyList {
+    entries: Mutex<HashMap<IpAddr, Penalty>>,
+}
+
+#[derive(Debug)]
+struct Penalty {
+    offenses: u32,
+    until: Instant,
+}
+
+impl PenaltyList {
+    pub fn new() -> Self {
+        PenaltyList::default()
+    }
+
+    /// Record an offense by `source`, returns how many it committed while penalized
+    pub fn penalize(&self, source: IpAddr) -> u32 {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+
+        let expired = |penalty: &Penalty| penalty.until <= now;
+        if !bounded::make_room(&mut entries, &source, MAX_PENALIZED, expired) {
+            return 0;
+        }
+
+        let penalty = entries.entry(source).or_insert(Penalty {
+            offenses: 0,
+            until: now,
+        });
+        if penalty.until <= now {
+            penalty.offenses = 0;
+        }
+        penalty.offenses += 1;
+        penalty.until = now + PENALTY_DURATION;
+        penalty.offenses
+    }
+
+    /// Offenses of `source` during its current penalty, 0 when it is not penalized
+    pub fn offenses(&self, source: IpAddr) -> u32 {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(&source) {
+            Some(penalty) if penalty.until > Instant::now() => penalty.offenses,
+            _ => 0,
+        }
+    }
+
+    pub fn is_penalized(&self, source: IpAddr) -> bool {
+        self.offenses(source) > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_penalties_count_per_source() {
+        let penalties = PenaltyList::new();
+        let attacker: IpAddr = "192.0.2.1".parse().unwrap();
+        let bystander: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert_eq!(penalties.penalize(attacker), 1);
+        assert_eq!(penalties.penalize(attacker), 2);
+        assert_eq!(penalties.offenses(attacker), 2);
+        assert!(penalties.is_penalized(attacker));
+        assert!(!penalties.is_penalized(bystander));
+    }
+
+    #[test]
+    fn test_parse_qr_policy() {
+        assert_eq!("drop".parse(), Ok(QrPolicy::Drop));
+        assert_eq!("log".parse(), Ok(QrPolicy::Log));
+        assert_eq!("penalize".parse(), Ok(QrPolicy::Penalize));
+        assert!("ignore".parse::<QrPolicy>().is_err());
+    }
+}
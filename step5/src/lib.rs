@@ -0,0 +1,20 @@
+// src/lib.rs
+pub mod bounded;
+pub mod cache;
+pub mod dns;
+pub mod edns;
+pub mod guard;
+pub mod mmap_zone;
+pub mod pool;
+pub mod rdata;
+pub mod reload;
+pub mod resolver;
+pub mod rng;
+pub mod server;
+pub mod srv;
+pub mod synthesized;
+pub mod tcp;
+pub mod throttle;
+pub mod transport;
+pub mod udp;
+pub mod zone;
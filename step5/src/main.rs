@@ -1,97 +1,205 @@
 // src/main.rs
-use std::net::UdpSocket;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
-mod dns;
-use dns::{Header, ResourceRecord};
-
-fn forward_query(query: &[u8]) -> Result<Vec<u8>, String> {
-    let resolver = "8.8.8.8:53";
-    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
-    socket
-        .set_read_timeout(Some(Duration::from_secs(5)))
-        .map_err(|e| e.to_string())?;
-    socket.send_to(query, resolver).map_err(|e| e.to_string())?;
-    let mut buf = [0; 512];
-    let (len, _) = socket.recv_from(&mut buf).map_err(|e| e.to_string())?;
-    Ok(buf[..len].to_vec())
+use clap::{Parser, Subcommand};
+use dns_server::dns::{Class, ErrorCondition, Message, Name};
+use dns_server::guard::QrPolicy;
+use dns_server::mmap_zone::MmapZone;
+use dns_server::pool::ThreadPool;
+use dns_server::reload::{ReloadPolicy, ReloadableZone, SharedSource};
+use dns_server::server::Server;
+use dns_server::synthesized::SynthesizedZone;
+use dns_server::tcp::serve_tcp;
+use dns_server::throttle::Throttle;
+use dns_server::udp::serve_udp;
+use dns_server::zone::Zone;
+
+#[derive(Parser)]
+#[command(about = "A small DNS server")]
+struct Args {
+    /// Answer queries from this zone file, names it does not contain are forwarded to --upstream
+    #[arg(long)]
+    zone: Option<PathBuf>,
+
+    /// Answer queries from a binary zone built with `build-zone`, memory-mapped for large zones
+    #[arg(long, conflicts_with = "zone")]
+    mmap_zone: Option<PathBuf>,
+
+    /// Answer names like 10-0-0-5.<domain> with the address they contain instead of using a zone
+    #[arg(long, value_name = "DOMAIN", conflicts_with_all = ["zone", "mmap_zone"])]
+    synthesize: Option<String>,
+
+    /// What queries get while the zone is reloaded after a SIGHUP: serve-stale or servfail
+    #[arg(long, default_value = "serve-stale")]
+    reload_policy: ReloadPolicy,
+
+    /// Also accept queries over TCP on port 1053
+    #[arg(long)]
+    tcp: bool,
+
+    /// Resolver to forward queries to, may be repeated. Defaults to 8.8.8.8:53 without a zone
+    #[arg(long)]
+    upstream: Vec<SocketAddr>,
+
+    /// Identifier of this server, sent to clients that ask for it with the EDNS NSID option
+    #[arg(long)]
+    nsid: Option<String>,
+
+    /// Number of threads answering queries. Defaults to the number of available CPUs
+    #[arg(long)]
+    workers: Option<usize>,
+
+    /// Class of queries to answer, may be repeated. Queries in other classes are refused
+    #[arg(long = "class", value_name = "CLASS", default_value = "IN")]
+    classes: Vec<Class>,
+
+    /// What happens to sources sending responses instead of queries: drop, log or penalize
+    #[arg(long, default_value = "log")]
+    qr_policy: QrPolicy,
+
+    /// Delay UDP responses to sources sending more than this many queries per second
+    #[arg(long, value_name = "QPS")]
+    throttle_above: Option<u32>,
+
+    /// How long responses to throttled sources are held back, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = 100)]
+    throttle_delay: u64,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a zone file into the binary format used by --mmap-zone
+    BuildZone { input: PathBuf, output: PathBuf },
+}
+
+fn load_zone(args: &Args) -> Result<SharedSource, ErrorCondition> {
+    load_zone_from(args.zone.clone(), args.mmap_zone.clone())
+}
+
+fn load_zone_from(
+    zone: Option<PathBuf>,
+    mmap_zone: Option<PathBuf>,
+) -> Result<SharedSource, ErrorCondition> {
+    match (zone, mmap_zone) {
+        (Some(path), _) => Ok(Arc::new(Zone::from_file(path)?)),
+        (None, Some(path)) => Ok(Arc::new(MmapZone::open(path)?)),
+        (None, None) => Err(ErrorCondition::ZoneErr {
+            line: 0,
+            reason: "no zone configured".to_string(),
+        }),
+    }
 }
 
 fn main() {
-    let socket = UdpSocket::bind("0.0.0.0:1053").expect("Could not bind to port 1053");
-    let mut buf = [0; 512];
+    let args = Args::parse();
 
-    println!("DNS server is running at port 1053");
-    println!("Forwarding queries to 8.8.8.8");
+    if let Some(Command::BuildZone { input, output }) = &args.command {
+        let result = Zone::from_file(input).and_then(|zone| MmapZone::build(&zone, output));
+        if let Err(e) = result {
+            eprintln!("Could not build binary zone {}: {}", output.display(), e);
+            std::process::exit(1);
+        }
+        println!("Wrote binary zone to {}", output.display());
+        return;
+    }
 
-    loop {
-        let (len, addr) = match socket.recv_from(&mut buf) {
-            Ok(v) => v,
+    let zone = if args.zone.is_some() || args.mmap_zone.is_some() {
+        match load_zone(&args) {
+            Ok(source) => Some(Arc::new(ReloadableZone::new(source, args.reload_policy))),
             Err(e) => {
-                eprintln!("Failed to receive: {}", e);
-                continue;
+                eprintln!("Could not load zone: {}", e);
+                std::process::exit(1);
             }
-        };
-
-        let query = &buf[..len];
+        }
+    } else {
+        None
+    };
 
-        if let Ok(header) = Header::from_bytes(query) {
-            println!(
-                "\nQuery from {} (ID: {:#06x}, questions: {})",
-                addr, header.id, header.qdcount
-            );
+    let synthesized = match args.synthesize.as_deref().map(Name::parse) {
+        Some(Ok(domain)) => Some(Arc::new(SynthesizedZone::new(domain)) as SharedSource),
+        Some(Err(e)) => {
+            eprintln!("Invalid domain for --synthesize: {}", e);
+            std::process::exit(1);
         }
+        None => None,
+    };
 
-        match forward_query(query) {
-            Ok(response) => {
-                if let Ok(resp_header) = Header::from_bytes(&response) {
-                    println!(
-                        "Response: {} answer(s), rcode={}",
-                        resp_header.ancount, resp_header.rcode
-                    );
-
-                    // Skip header and question section to reach the answer section
-                    let mut offset = 12;
-                    for _ in 0..resp_header.qdcount {
-                        while offset < response.len() {
-                            let byte = response[offset];
-                            if byte == 0 {
-                                offset += 1;
-                                break;
-                            } else if byte & 0b11000000 == 0b11000000 {
-                                offset += 2;
-                                break;
-                            } else {
-                                offset += 1 + byte as usize;
-                            }
-                        }
-                        offset += 4; // skip QTYPE + QCLASS
-                    }
-
-                    for i in 0..resp_header.ancount {
-                        match ResourceRecord::from_bytes(&response, offset) {
-                            Ok((record, next_offset)) => {
-                                println!(
-                                    "  Answer {}: {} -> {:?}",
-                                    i + 1,
-                                    record.name,
-                                    record.rdata
-                                );
-                                offset = next_offset;
-                            }
-                            Err(e) => {
-                                eprintln!("  Failed to parse answer {}: {}", i + 1, e);
-                                break;
-                            }
-                        }
-                    }
-                }
-
-                if let Err(e) = socket.send_to(&response, addr) {
-                    eprintln!("Failed to send response to {}: {}", addr, e);
-                }
+    #[cfg(unix)]
+    if let Some(zone) = &zone {
+        let zone = zone.clone();
+        let zone_args = (args.zone.clone(), args.mmap_zone.clone());
+        dns_server::reload::watch_sighup(move || {
+            println!("Received SIGHUP, reloading zone");
+            let (zone_path, mmap_zone_path) = zone_args.clone();
+            match zone.reload(|| load_zone_from(zone_path, mmap_zone_path)) {
+                Ok(()) => println!("Zone reloaded"),
+                Err(e) => eprintln!("Zone reload failed, keeping the old zone: {}", e),
             }
-            Err(e) => eprintln!("Forward failed: {}", e),
-        }
+        });
+    }
+
+    let mut upstreams = args.upstream;
+    if upstreams.is_empty() && zone.is_none() && synthesized.is_none() {
+        upstreams.push("8.8.8.8:53".parse().unwrap());
+    }
+
+    println!("DNS server is running at port 1053");
+    if zone.is_some() {
+        println!("Answering queries from the zone file");
+    }
+    if let Some(domain) = &args.synthesize {
+        println!(
+            "Answering names under {} with the address they contain",
+            domain
+        );
+    }
+    for upstream in &upstreams {
+        println!("Forwarding queries to {}", upstream);
+    }
+
+    let source = zone.map(|zone| zone as SharedSource).or(synthesized);
+    let mut server = Server::new(source, upstreams)
+        .with_qr_policy(args.qr_policy)
+        .with_classes(args.classes);
+    if let Some(nsid) = args.nsid {
+        server = server.with_nsid(nsid.into_bytes());
+    }
+    let server = Arc::new(server);
+
+    if args.tcp {
+        let server = server.clone();
+        std::thread::spawn(move || {
+            let handler = move |query: Message| server.respond(&query);
+            if let Err(e) = serve_tcp("0.0.0.0:1053", handler) {
+                eprintln!("TCP listener failed: {}", e);
+            }
+        });
+        println!("Accepting TCP queries at port 1053");
+    }
+
+    let pool = match args.workers {
+        Some(workers) => ThreadPool::new(workers),
+        None => ThreadPool::with_available_parallelism(),
+    };
+    println!("Answering queries with {} worker thread(s)", pool.size());
+
+    let throttle = args.throttle_above.map(|threshold| {
+        println!(
+            "Delaying responses to sources above {} queries per second by {}ms",
+            threshold, args.throttle_delay
+        );
+        Throttle::new(threshold, Duration::from_millis(args.throttle_delay))
+    });
+
+    let handler = move |query: &[u8], source: SocketAddr| server.handle(query, source);
+    if let Err(e) = serve_udp("0.0.0.0:1053", &pool, throttle, handler) {
+        eprintln!("Could not serve on port 1053: {}", e);
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,517 @@
+// src/mmap_zone.rs
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::dns::{Class, ErrorCondition, Name, ResourceRecord, Type};
+use crate::zone::{AnswerSource, Zone};
+
+/// A pre-built zone that is memory-mapped instead of loaded into a `HashMap`, so only the pages
+/// touched by a lookup are read from disk. The file is built from a text zone with
+/// [`MmapZone::build`] and has the following layout, all integers big-endian:
+///
+/// ```text
+/// magic    8 bytes      "DNSZONE1"
+/// count    u32          number of records
+/// empty    u32          number of empty non-terminals
+/// apex     name length (u8), name of the zone apex, empty without records
+/// index    (count + empty) * u64  offset of each entry, sorted by (name, type)
+/// entries  name length (u8), name, type (u16), class (u16), ttl (u32), rdlength (u16), rdata
+/// ```
+///
+/// Names are stored in canonical form, see [`Name::to_canonical_bytes`]. An empty non-terminal,
+/// a name without records that exists because names below it do, is an entry of type 0 with
+/// all other fields zero.
+///
+/// A lookup is a binary search over the index comparing names in place, so a name that is not
+/// in the zone costs O(log n) record reads and no allocation.
+pub struct MmapZone {
+    map: Mmap,
+    count: usize,
+    entries: usize,
+    apex: Option<Name>,
+    index_start: usize,
+}
+
+const MAGIC: &[u8; 8] = b"DNSZONE1";
+const APEX_START: usize = MAGIC.len() + 8;
+
+/// Size of an offset in the index
+const OFFSET_LEN: usize = 8;
+
+/// The type of the entries marking empty non-terminals, 0 is reserved and never a record type
+const EMPTY_NON_TERMINAL: u16 = 0;
+
+impl MmapZone {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MmapZone, ErrorCondition> {
+        let map = Mmap::open(&File::open(path)?)?;
+
+        if map.len() <= APEX_START || &map[..MAGIC.len()] != MAGIC {
+            return Err(ErrorCondition::DeserializationErr(
+                "Not a binary zone file".to_string(),
+            ));
+        }
+
+        let count = read_u32(&map, MAGIC.len()) as usize;
+        let empty = read_u32(&map, MAGIC.len() + 4) as usize;
+        let index_start = APEX_START + 1 + map[APEX_START] as usize;
+        let apex = match map.get(APEX_START + 1..index_start) {
+            Some([]) => None,
+            Some(apex) => Some(Name::from_message(apex, 0)?.0),
+            None => {
+                return Err(ErrorCondition::DeserializationErr(
+                    "Binary zone apex is truncated".to_string(),
+                ))
+            }
+        };
+
+        // The counts come from the file, on 32-bit targets a corrupt one could overflow them
+        let entries = count.checked_add(empty);
+        let index_end = entries
+            .and_then(|entries| entries.checked_mul(OFFSET_LEN))
+            .and_then(|len| len.checked_add(index_start));
+        let entries = match (entries, index_end) {
+            (Some(entries), Some(end)) if end <= map.len() => entries,
+            _ => {
+                return Err(ErrorCondition::DeserializationErr(
+                    "Binary zone index is truncated".to_string(),
+                ))
+            }
+        };
+
+        Ok(MmapZone {
+            map,
+            count,
+            entries,
+            apex,
+            index_start,
+        })
+    }
+
+    // Write the records of `zone` in the binary format expected by `open`
+    pub fn build<P: AsRef<Path>>(zone: &Zone, path: P) -> Result<(), ErrorCondition> {
+        // Empty non-terminals are entries without a record
+        let mut entries: Vec<(Vec<u8>, u16, Option<&ResourceRecord>)> = zone
+            .records()
+            .map(|record| {
+                let name = record.name.to_canonical_bytes();
+                (name, type_code(&record.rtype), Some(record))
+            })
+            .collect();
+        let records = entries.len();
+        let empty = zone.empty_non_terminals();
+        entries.extend(
+            empty
+                .into_iter()
+                .map(|name| (name, EMPTY_NON_TERMINAL, None)),
+        );
+        entries.sort_by(|(a_name, a_type, _), (b_name, b_type, _)| {
+            a_name.cmp(b_name).then(a_type.cmp(b_type))
+        });
+
+        let apex = zone
+            .apex()
+            .map(|apex| apex.to_canonical_bytes())
+            .unwrap_or_default();
+
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&count(records)?.to_be_bytes())?;
+        out.write_all(&count(entries.len() - records)?.to_be_bytes())?;
+        out.write_all(&[name_len(&apex)?])?;
+        out.write_all(&apex)?;
+
+        let mut offset = (APEX_START + 1 + apex.len() + entries.len() * OFFSET_LEN) as u64;
+        for (name, _, record) in &entries {
+            out.write_all(&offset.to_be_bytes())?;
+            let rdata = record.map_or(0, |record| record.rdata.len());
+            offset += (1 + name.len() + 10 + rdata) as u64;
+        }
+
+        for (name, rtype, record) in &entries {
+            out.write_all(&[name_len(name)?])?;
+            out.write_all(name)?;
+            out.write_all(&rtype.to_be_bytes())?;
+            match record {
+                Some(record) => {
+                    out.write_all(&record.rclass.to_bytes())?;
+                    out.write_all(&record.ttl.to_be_bytes())?;
+                    out.write_all(&record.rdlength.to_be_bytes())?;
+                    out.write_all(&record.rdata)?;
+                }
+                None => out.write_all(&[0; 8])?,
+            }
+        }
+
+        out.flush()?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // The name and type of the i-th record without copying anything out of the map
+    fn key(&self, i: usize) -> Option<(&[u8], u16)> {
+        let start = self.record_offset(i)?;
+        let name_len = *self.map.get(start)? as usize;
+        let name = self.map.get(start + 1..start + 1 + name_len)?;
+        let rtype = self.map.get(start + 1 + name_len..start + 3 + name_len)?;
+        Some((name, u16::from_be_bytes([rtype[0], rtype[1]])))
+    }
+
+    fn record_offset(&self, i: usize) -> Option<usize> {
+        let at = self.index_start + i * OFFSET_LEN;
+        let offset = self.map.get(at..at + OFFSET_LEN)?;
+        usize::try_from(u64::from_be_bytes(offset.try_into().unwrap())).ok()
+    }
+
+    fn record(&self, i: usize) -> Option<ResourceRecord> {
+        let start = self.record_offset(i)?;
+        let name_len = *self.map.get(start)? as usize;
+        let name = self.map.get(start + 1..start + 1 + name_len)?;
+
+        let fixed = start + 1 + name_len;
+        let fields = self.map.get(fixed..fixed + 10)?;
+        let rdlength = u16::from_be_bytes([fields[8], fields[9]]);
+        let rdata = self.map.get(fixed + 10..fixed + 10 + rdlength as usize)?;
+
+        Some(ResourceRecord {
+            name: Name::from_message(name, 0).ok()?.0,
+            rtype: Type::from_bytes(&fields[0..2]).ok()?,
+            rclass: Class::from_bytes(&fields[2..4]).ok()?,
+            ttl: u32::from_be_bytes(fields[4..8].try_into().unwrap()),
+            rdlength,
+            rdata: rdata.to_vec(),
+        })
+    }
+
+    // Index of the first record whose key is not less than (name, rtype)
+    fn lower_bound(&self, name: &[u8], rtype: u16) -> usize {
+        let (mut low, mut high) = (0, self.entries);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let less = match self.key(mid) {
+                Some((n, t)) => n.cmp(name).then(t.cmp(&rtype)) == Ordering::Less,
+                None => false,
+            };
+            if less {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+}
+
+impl AnswerSource for MmapZone {
+    fn rrset(&self, name: &Name, qtype: &Type) -> Vec<ResourceRecord> {
+        let name = name.to_canonical_bytes();
+        let rtype = type_code(qtype);
+        if rtype == EMPTY_NON_TERMINAL {
+            return Vec::new();
+        }
+
+        (self.lower_bound(&name, rtype)..self.entries)
+            .take_while(|&i| self.key(i) == Some((&name, rtype)))
+            .filter_map(|i| self.record(i))
+            .collect()
+    }
+
+    fn contains_name(&self, name: &Name) -> bool {
+        let name = name.to_canonical_bytes();
+        let i = self.lower_bound(&name, 0);
+        matches!(self.key(i), Some((n, _)) if n == name)
+    }
+
+    fn apex(&self) -> Option<Name> {
+        self.apex.clone()
+    }
+}
+
+fn type_code(rtype: &Type) -> u16 {
+    u16::from_be_bytes(rtype.to_bytes())
+}
+
+fn read_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+// The counts in the header are 32 bits, more entries than that don't fit in the format
+fn count(n: usize) -> Result<u32, ErrorCondition> {
+    u32::try_from(n)
+        .map_err(|_| ErrorCondition::SerializationErr(format!("Too many entries: {}", n)))
+}
+
+// Names are prefixed with their length, the 255 octet limit keeps it within a u8
+fn name_len(name: &[u8]) -> Result<u8, ErrorCondition> {
+    u8::try_from(name.len()).map_err(|_| ErrorCondition::NameTooLong(name.len()))
+}
+
+/// A read-only memory mapping of a whole file.
+struct Mmap {
+    ptr: *const u8,
+    len: usize,
+    #[cfg(not(unix))]
+    data: Vec<u8>,
+}
+
+// The mapping is read-only and never changes after it is created
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+#[cfg(unix)]
+mod sys {
+    use std::os::raw::{c_int, c_void};
+
+    // As the C library declares off_t: a long on 32-bit glibc and Android, 64 bits elsewhere
+    #[cfg(all(
+        target_pointer_width = "32",
+        any(target_os = "android", all(target_os = "linux", target_env = "gnu"))
+    ))]
+    #[allow(non_camel_case_types)]
+    pub type off_t = i32;
+    #[cfg(not(all(
+        target_pointer_width = "32",
+        any(target_os = "android", all(target_os = "linux", target_env = "gnu"))
+    )))]
+    #[allow(non_camel_case_types)]
+    pub type off_t = i64;
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_PRIVATE: c_int = 2;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    extern "C" {
+        pub fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: off_t,
+        ) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+impl Mmap {
+    #[cfg(unix)]
+    fn open(file: &File) -> Result<Mmap, ErrorCondition> {
+        use std::os::unix::io::AsRawFd;
+
+        // A file larger than the address space can't be mapped as a whole
+        let len = usize::try_from(file.metadata()?.len()).map_err(|_| {
+            ErrorCondition::DeserializationErr("Binary zone is too large to map".to_string())
+        })?;
+        if len == 0 {
+            // mmap refuses empty mappings
+            return Ok(Mmap {
+                ptr: std::ptr::NonNull::dangling().as_ptr(),
+                len,
+            });
+        }
+
+        let ptr = unsafe {
+            sys::mmap(
+                std::ptr::null_mut(),
+                len,
+                sys::PROT_READ,
+                sys::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == sys::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(Mmap {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    // Without mmap the file is read into memory, which keeps the format usable everywhere
+    #[cfg(not(unix))]
+    fn open(file: &File) -> Result<Mmap, ErrorCondition> {
+        use std::io::Read;
+
+        let mut data = Vec::new();
+        (&*file).read_to_end(&mut data)?;
+        Ok(Mmap {
+            ptr: data.as_ptr(),
+            len: data.len(),
+            data,
+        })
+    }
+}
+
+impl std::ops::Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe {
+                sys::munmap(self.ptr as *mut _, self.len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::LookupResult;
+
+    fn build_zone(test: &str, text: &str) -> (MmapZone, std::path::PathBuf) {
+        let path =
+            std::env::temp_dir().join(format!("dns-server-{}-{}.bin", test, std::process::id()));
+        MmapZone::build(&Zone::parse(text).unwrap(), &path).unwrap();
+        (MmapZone::open(&path).unwrap(), path)
+    }
+
+    #[test]
+    fn test_build_and_query_mmap_zone() {
+        let (zone, path) = build_zone(
+            "query",
+            "
+            www.example.com   A     172.67.221.148  60
+            www.example.com   A     172.67.221.149  60
+            www.example.com   AAAA  2606:4700::1    60
+            example.com       A     10.0.0.1        300
+            mail.example.com  A     10.0.0.2        300
+            ",
+        );
+        assert_eq!(zone.len(), 5);
+        assert_eq!(zone.apex(), Some(Name::parse("example.com").unwrap()));
+
+        let name = |n: &str| Name::parse(n).unwrap();
+        let a = zone.rrset(&name("WWW.example.com."), &Type::A);
+        assert_eq!(a.len(), 2);
+        assert_eq!(a[0].name.to_string(), "www.example.com");
+        assert_eq!(a[0].rdata, vec![172, 67, 221, 148]);
+        assert_eq!(a[1].rdata, vec![172, 67, 221, 149]);
+
+        let aaaa = zone.rrset(&name("www.example.com"), &Type::AAAA);
+        assert_eq!(aaaa.len(), 1);
+        assert_eq!(aaaa[0].rdlength, 16);
+
+        let apex = zone.rrset(&name("example.com"), &Type::A);
+        assert_eq!(apex.len(), 1);
+        assert_eq!(apex[0].ttl, 300);
+
+        assert!(zone.rrset(&name("example.com"), &Type::AAAA).is_empty());
+        assert!(zone.contains_name(&name("mail.example.com")));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_non_ascii_names_in_mmap_zone() {
+        let (zone, path) = build_zone("non-ascii", "café.example.com  A  10.0.0.1  60");
+
+        let name = Name::parse("CAFé.example.com").unwrap();
+        assert!(zone.contains_name(&name));
+        let records = zone.rrset(&name, &Type::A);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].name, Name::parse("café.example.com").unwrap());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_empty_non_terminal_in_mmap_zone() {
+        let (zone, path) = build_zone(
+            "empty-non-terminal",
+            "
+            example.com       A  10.0.0.1  60
+            a.b.example.com   A  10.0.0.2  60
+            *.example.com     A  10.0.0.3  60
+            ",
+        );
+        let name = |n: &str| Name::parse(n).unwrap();
+        assert_eq!(zone.len(), 3);
+
+        assert!(zone.contains_name(&name("b.example.com")));
+        let empty = zone.lookup(&name("b.example.com"), &Type::A);
+        assert!(matches!(empty, LookupResult::NoData));
+        let found = zone.lookup(&name("a.b.example.com"), &Type::A);
+        assert!(matches!(found, LookupResult::Found(records) if records.len() == 1));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_names_in_mmap_zone() {
+        let (zone, path) = build_zone(
+            "missing",
+            "
+            b.example.com  A  10.0.0.1  60
+            d.example.com  A  10.0.0.2  60
+            ",
+        );
+
+        // Before the first, between two and after the last record in sort order
+        for name in [
+            "a.example.com",
+            "c.example.com",
+            "e.example.com",
+            "example.org",
+        ] {
+            let parsed = Name::parse(name).unwrap();
+            assert!(!zone.contains_name(&parsed), "{}", name);
+            assert!(zone.rrset(&parsed, &Type::A).is_empty(), "{}", name);
+        }
+
+        // The apex has no records, but exists as the parent of the others
+        let apex = Name::parse("example.com").unwrap();
+        assert!(zone.contains_name(&apex));
+        assert!(zone.rrset(&apex, &Type::A).is_empty());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_empty_and_invalid_files() {
+        let (zone, path) = build_zone("empty", "");
+        assert!(zone.is_empty());
+        assert!(zone.apex().is_none());
+        assert!(!zone.contains_name(&Name::parse("example.com").unwrap()));
+        std::fs::remove_file(&path).unwrap();
+
+        std::fs::write(&path, b"not a zone").unwrap();
+        assert!(MmapZone::open(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_header_claiming_too_many_entries() {
+        let path =
+            std::env::temp_dir().join(format!("dns-server-too-many-{}.bin", std::process::id()));
+        let mut file = MAGIC.to_vec();
+        file.extend_from_slice(&u32::MAX.to_be_bytes());
+        file.extend_from_slice(&u32::MAX.to_be_bytes());
+        file.push(0); // no apex
+        std::fs::write(&path, file).unwrap();
+
+        assert!(matches!(
+            MmapZone::open(&path),
+            Err(ErrorCondition::DeserializationErr(_))
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+}
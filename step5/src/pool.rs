@@ -0,0 +1,136 @@
+// src/pool.rs
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Jobs that may wait for each worker before new ones are turned away
+const QUEUE_PER_WORKER: usize = 64;
+
+/// A fixed number of worker threads taking jobs from a shared queue. The queue is bounded, so
+/// a flood of work faster than the workers can keep up with is dropped instead of piling up in
+/// memory. Dropping the pool lets the workers finish the queued jobs and waits for them.
+pub struct ThreadPool {
+    workers: Vec<JoinHandle<()>>,
+    sender: Option<SyncSender<Job>>,
+}
+
+impl ThreadPool {
+    /// Start `size` workers, at least one
+    pub fn new(size: usize) -> Self {
+        let size = size.max(1);
+        ThreadPool::with_queue_capacity(size, size * QUEUE_PER_WORKER)
+    }
+
+    /// Start `size` workers with room for `capacity` jobs waiting for them, at least one each
+    pub fn with_queue_capacity(size: usize, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(capacity.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size.max(1))
+            .map(|_| {
+                let receiver = receiver.clone();
+                std::thread::spawn(move || work(&receiver))
+            })
+            .collect();
+
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    /// A pool with one worker per CPU the process may use
+    pub fn with_available_parallelism() -> Self {
+        ThreadPool::new(std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Queue `job` for the next free worker. Returns false without running it when the queue
+    /// is full.
+    pub fn execute<F>(&self, job: F) -> bool
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let Some(sender) = &self.sender else {
+            return false;
+        };
+        // Disconnected only once every worker is gone, which can't happen while we exist
+        match sender.try_send(Box::new(job)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+// Run jobs until the pool is dropped. A panicking job must not take its worker down with it,
+// or every bad packet would leave the pool a thread smaller.
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match receiver.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        if catch_unwind(AssertUnwindSafe(job)).is_err() {
+            eprintln!("A worker job panicked");
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_jobs_run_and_survive_panics() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(2);
+        assert_eq!(pool.size(), 2);
+
+        pool.execute(|| panic!("bad job"));
+        for _ in 0..10 {
+            let done = done.clone();
+            pool.execute(move || {
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        drop(pool);
+
+        assert_eq!(done.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_full_queue_drops_jobs() {
+        let pool = ThreadPool::with_queue_capacity(1, 2);
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started, running) = mpsc::channel();
+
+        // Keep the only worker busy until the end of the test
+        assert!(pool.execute(move || {
+            started.send(()).unwrap();
+            let _ = blocked.recv();
+        }));
+        running.recv().unwrap();
+
+        assert!(pool.execute(|| {}));
+        assert!(pool.execute(|| {}));
+        assert!(!pool.execute(|| panic!("should have been dropped")));
+
+        drop(release);
+    }
+}
@@ -0,0 +1,232 @@
+// src/rdata.rs
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::dns::{ErrorCondition, Name, Type};
+
+/// The RDATA of a resource record, decoded for the types this server understands.
+/// Anything else is kept as the raw bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(Name),
+    Mx { preference: u16, exchange: Name },
+    Txt(Vec<String>),
+    Raw(Vec<u8>),
+}
+
+impl RData {
+    /// Decode the `rdlength` bytes of RDATA at `start` of the message `msg`. Names in CNAME and
+    /// MX records may be compressed, so the whole message is needed to follow their pointers.
+    pub fn parse(
+        rtype: &Type,
+        msg: &[u8],
+        start: usize,
+        rdlength: u16,
+    ) -> Result<Self, ErrorCondition> {
+        let end = start + rdlength as usize;
+        let rdata = msg.get(start..end).ok_or_else(|| {
+            ErrorCondition::DeserializationErr("RDATA runs past the end of the message".to_string())
+        })?;
+
+        // A name has to end inside the RDATA, even when it continues elsewhere through a pointer
+        let name_at = |offset: usize| -> Result<Name, ErrorCondition> {
+            let (name, next) = Name::from_message(msg, offset)?;
+            if next != end {
+                return Err(ErrorCondition::DeserializationErr(format!(
+                    "Name in {:?} RDATA does not end with the record",
+                    rtype
+                )));
+            }
+            Ok(name)
+        };
+
+        match rtype {
+            Type::A => <[u8; 4]>::try_from(rdata)
+                .map(|octets| RData::A(Ipv4Addr::from(octets)))
+                .map_err(|_| wrong_length(rtype, rdlength)),
+            Type::AAAA => <[u8; 16]>::try_from(rdata)
+                .map(|octets| RData::Aaaa(Ipv6Addr::from(octets)))
+                .map_err(|_| wrong_length(rtype, rdlength)),
+            Type::CNAME => Ok(RData::Cname(name_at(start)?)),
+            Type::MX => {
+                let preference = rdata
+                    .get(..2)
+                    .ok_or_else(|| wrong_length(rtype, rdlength))?;
+                Ok(RData::Mx {
+                    preference: u16::from_be_bytes([preference[0], preference[1]]),
+                    exchange: name_at(start + 2)?,
+                })
+            }
+            Type::TXT => {
+                // One or more character strings, each a length octet followed by its text
+                let mut strings = Vec::new();
+                let mut index = 0;
+                while index < rdata.len() {
+                    let len = rdata[index] as usize;
+                    let text = rdata
+                        .get(index + 1..index + 1 + len)
+                        .ok_or_else(|| wrong_length(rtype, rdlength))?;
+                    strings.push(String::from_utf8_lossy(text).into_owned());
+                    index += 1 + len;
+                }
+                Ok(RData::Txt(strings))
+            }
+            _ => Ok(RData::Raw(rdata.to_vec())),
+        }
+    }
+
+    // Encode in wire format, names are written uncompressed
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ErrorCondition> {
+        match self {
+            RData::A(address) => Ok(address.octets().to_vec()),
+            RData::Aaaa(address) => Ok(address.octets().to_vec()),
+            RData::Cname(name) => name.to_bytes(),
+            RData::Mx {
+                preference,
+                exchange,
+            } => {
+                let mut buf = preference.to_be_bytes().to_vec();
+                buf.extend_from_slice(&exchange.to_bytes()?);
+                Ok(buf)
+            }
+            RData::Txt(strings) => {
+                let mut buf = Vec::new();
+                for text in strings {
+                    let len = u8::try_from(text.len()).map_err(|_| {
+                        ErrorCondition::SerializationErr(format!(
+                            "TXT string of {} bytes is too long",
+                            text.len()
+                        ))
+                    })?;
+                    buf.push(len);
+                    buf.extend_from_slice(text.as_bytes());
+                }
+                Ok(buf)
+            }
+            RData::Raw(data) => Ok(data.clone()),
+        }
+    }
+}
+
+/// The RDATA in zone file notation, e.g. `10 mx1.example.com.` for an MX record.
+/// Undecoded RDATA uses the generic `\# <length> <hex>` notation of RFC 3597.
+impl fmt::Display for RData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RData::A(address) => write!(f, "{}", address),
+            RData::Aaaa(address) => write!(f, "{}", address),
+            RData::Cname(name) => write!(f, "{}.", name),
+            RData::Mx {
+                preference,
+                exchange,
+            } => write!(f, "{} {}.", preference, exchange),
+            RData::Txt(strings) => {
+                let quoted: Vec<String> = strings.iter().map(|s| format!("{:?}", s)).collect();
+                write!(f, "{}", quoted.join(" "))
+            }
+            RData::Raw(data) => {
+                write!(f, "\\# {} ", data.len())?;
+                data.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+            }
+        }
+    }
+}
+
+fn wrong_length(rtype: &Type, rdlength: u16) -> ErrorCondition {
+    ErrorCondition::DeserializationErr(format!("Malformed {:?} RDATA of {} bytes", rtype, rdlength))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Class, Header, Message, Question, ResourceRecord};
+
+    fn name(n: &str) -> Name {
+        Name::parse(n).unwrap()
+    }
+
+    // Parse the RDATA of every answer in `bytes`, a message with a single question
+    fn answers(bytes: &[u8]) -> Vec<RData> {
+        let (_, mut offset) = Question::from_message(bytes, 12).unwrap();
+        let ancount = Header::from_bytes(bytes).unwrap().ancount;
+        (0..ancount)
+            .map(|_| {
+                let (record, next) = ResourceRecord::from_bytes(bytes, offset).unwrap();
+                offset = next;
+                RData::parse(&record.rtype, &record.rdata, 0, record.rdlength).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_compressed_names() {
+        // mail.example.com MX 10 mx1.example.com, where example.com in the exchange is a
+        // pointer to the question name. The owner names of the answers are pointers too.
+        let header = Header::from_bytes(&[0x43, 0xE6, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 0]);
+        let mut bytes = header.unwrap().to_bytes();
+        bytes.extend_from_slice(&name("mail.example.com").to_bytes().unwrap());
+        bytes.extend_from_slice(&[0, 15, 0, 1]);
+        let answer = |rtype: u8, rdata: &[u8]| {
+            let mut record = vec![0xC0, 12, 0, rtype, 0, 1, 0, 0, 0, 60, 0, rdata.len() as u8];
+            record.extend_from_slice(rdata);
+            record
+        };
+        bytes.extend(answer(15, &[0, 10, 3, b'm', b'x', b'1', 0xC0, 17]));
+        bytes.extend(answer(5, &[3, b'w', b'e', b'b', 0xC0, 17]));
+
+        let rdatas = answers(&bytes);
+        assert_eq!(
+            rdatas[0],
+            RData::Mx {
+                preference: 10,
+                exchange: name("mx1.example.com"),
+            }
+        );
+        assert_eq!(rdatas[0].to_string(), "10 mx1.example.com.");
+        assert_eq!(rdatas[1], RData::Cname(name("web.example.com")));
+
+        // A pointer is followed, but the name still has to end where the RDATA does
+        assert!(RData::parse(&Type::CNAME, &bytes, bytes.len() - 6, 4).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rdatas = [
+            (Type::A, RData::A(Ipv4Addr::new(10, 0, 0, 1))),
+            (Type::AAAA, RData::Aaaa("2001:db8::1".parse().unwrap())),
+            (Type::CNAME, RData::Cname(name("www.example.com"))),
+            (
+                Type::MX,
+                RData::Mx {
+                    preference: 20,
+                    exchange: name("mx2.example.com"),
+                },
+            ),
+            (
+                Type::TXT,
+                RData::Txt(vec!["v=spf1 -all".to_string(), String::new()]),
+            ),
+            (Type::SRV, RData::Raw(vec![0, 1, 0, 5, 0x13, 0xC4, 0])),
+        ];
+
+        let query = Message::query("example.com", Type::_ALL_).unwrap();
+        let mut response = query.response();
+        for (rtype, rdata) in &rdatas {
+            let bytes = rdata.to_bytes().unwrap();
+            response.answers.push(
+                ResourceRecord::new(name("example.com"), rtype.clone(), Class::IN, 60, bytes)
+                    .unwrap(),
+            );
+        }
+
+        let parsed = answers(&response.to_bytes().unwrap());
+        let expected: Vec<RData> = rdatas.into_iter().map(|(_, rdata)| rdata).collect();
+        assert_eq!(parsed, expected);
+
+        assert_eq!(expected[4].to_string(), "\"v=spf1 -all\" \"\"");
+        assert_eq!(expected[5].to_string(), "\\# 7 0001000513c400");
+        assert!(RData::parse(&Type::A, &[10, 0, 0], 0, 3).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+// src/reload.rs
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::dns::{ErrorCondition, Message, Name, ResourceRecord, Type, RCODE_SERVER_FAILURE};
+use crate::zone::{AnswerSource, LookupResult};
+
+pub type SharedSource = Arc<dyn AnswerSource + Send + Sync>;
+
+/// What queries get while a zone is being reloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReloadPolicy {
+    /// Keep answering from the old zone until the new one is swapped in
+    #[default]
+    ServeStale,
+    /// Answer SERVFAIL until the new zone is in place, never serving outdated data
+    ServFail,
+}
+
+impl FromStr for ReloadPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "serve-stale" => Ok(ReloadPolicy::ServeStale),
+            "servfail" => Ok(ReloadPolicy::ServFail),
+            _ => Err(format!(
+                "unknown reload policy '{}', expected serve-stale or servfail",
+                s
+            )),
+        }
+    }
+}
+
+/// A zone that can be replaced while the server is running, e.g. after a SIGHUP.
+/// The new zone is loaded next to the old one and swapped in under a write lock.
+pub struct ReloadableZone {
+    current: RwLock<SharedSource>,
+    reloading: AtomicBool,
+    policy: ReloadPolicy,
+}
+
+impl ReloadableZone {
+    pub fn new(source: SharedSource, policy: ReloadPolicy) -> Self {
+        ReloadableZone {
+            current: RwLock::new(source),
+            reloading: AtomicBool::new(false),
+            policy,
+        }
+    }
+
+    /// Replace the zone with the one returned by `load`. If loading fails the old zone stays.
+    pub fn reload<F>(&self, load: F) -> Result<(), ErrorCondition>
+    where
+        F: FnOnce() -> Result<SharedSource, ErrorCondition>,
+    {
+        self.reloading.store(true, Ordering::SeqCst);
+        let result = load().map(|source| {
+            *self.current.write().unwrap() = source;
+        });
+        self.reloading.store(false, Ordering::SeqCst);
+        result
+    }
+
+    pub fn is_reloading(&self) -> bool {
+        self.reloading.load(Ordering::SeqCst)
+    }
+
+    fn source(&self) -> SharedSource {
+        self.current.read().unwrap().clone()
+    }
+}
+
+impl AnswerSource for ReloadableZone {
+    fn rrset(&self, name: &Name, qtype: &Type) -> Vec<ResourceRecord> {
+        self.source().rrset(name, qtype)
+    }
+
+    fn contains_name(&self, name: &Name) -> bool {
+        self.source().contains_name(name)
+    }
+
+    fn apex(&self) -> Option<Name> {
+        self.source().apex()
+    }
+
+    // Look up in one snapshot of the zone, a reload halfway through must not mix old and new
+    fn lookup(&self, name: &Name, qtype: &Type) -> LookupResult {
+        self.source().lookup(name, qtype)
+    }
+
+    fn answer(&self, query: &Message) -> Message {
+        if self.policy == ReloadPolicy::ServFail && self.is_reloading() {
+            let mut response = query.response();
+            response.header.rcode = RCODE_SERVER_FAILURE;
+            return response;
+        }
+
+        self.source().answer(query)
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::os::raw::c_int;
+
+    pub const SIGHUP: c_int = 1;
+
+    extern "C" {
+        pub fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+}
+
+#[cfg(unix)]
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_sighup(_: std::os::raw::c_int) {
+    HANGUP.store(true, Ordering::SeqCst);
+}
+
+/// Call `on_hangup` from a background thread every time the process receives SIGHUP.
+/// The signal handler only sets a flag, the work happens outside of signal context.
+#[cfg(unix)]
+pub fn watch_sighup<F>(on_hangup: F)
+where
+    F: Fn() + Send + 'static,
+{
+    unsafe {
+        sys::signal(sys::SIGHUP, on_sighup);
+    }
+
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_millis(200));
+        if HANGUP.swap(false, Ordering::SeqCst) {
+            on_hangup();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::Zone;
+    use std::sync::mpsc;
+
+    fn zone(address: &str) -> SharedSource {
+        Arc::new(Zone::parse(&format!("www.example.com A {} 60", address)).unwrap())
+    }
+
+    fn query() -> Message {
+        Message::query("www.example.com", Type::A).unwrap()
+    }
+
+    #[test]
+    fn test_serve_stale_during_reload() {
+        let reloadable = ReloadableZone::new(zone("10.0.0.1"), ReloadPolicy::ServeStale);
+        let (started_tx, started_rx) = mpsc::channel();
+        let (finish_tx, finish_rx) = mpsc::channel::<()>();
+
+        std::thread::scope(|scope| {
+            let reloadable = &reloadable;
+            scope.spawn(move || {
+                reloadable
+                    .reload(|| {
+                        started_tx.send(()).unwrap();
+                        finish_rx.recv().unwrap();
+                        Ok(zone("10.0.0.2"))
+                    })
+                    .unwrap();
+            });
+
+            started_rx.recv().unwrap();
+            let during = reloadable.answer(&query());
+            assert_eq!(during.header.rcode, 0);
+            assert_eq!(during.answers[0].rdata, vec![10, 0, 0, 1]);
+            finish_tx.send(()).unwrap();
+        });
+
+        let after = reloadable.answer(&query());
+        assert_eq!(after.answers[0].rdata, vec![10, 0, 0, 2]);
+    }
+
+    #[test]
+    fn test_servfail_during_reload() {
+        let reloadable = ReloadableZone::new(zone("10.0.0.1"), ReloadPolicy::ServFail);
+        let (started_tx, started_rx) = mpsc::channel();
+        let (finish_tx, finish_rx) = mpsc::channel::<()>();
+
+        std::thread::scope(|scope| {
+            let reloadable = &reloadable;
+            scope.spawn(move || {
+                reloadable
+                    .reload(|| {
+                        started_tx.send(()).unwrap();
+                        finish_rx.recv().unwrap();
+                        Ok(zone("10.0.0.2"))
+                    })
+                    .unwrap();
+            });
+
+            started_rx.recv().unwrap();
+            let during = reloadable.answer(&query());
+            assert_eq!(during.header.rcode, RCODE_SERVER_FAILURE);
+            assert!(during.answers.is_empty());
+            finish_tx.send(()).unwrap();
+        });
+
+        let after = reloadable.answer(&query());
+        assert_eq!(after.header.rcode, 0);
+        assert_eq!(after.answers[0].rdata, vec![10, 0, 0, 2]);
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_old_zone() {
+        let reloadable = ReloadableZone::new(zone("10.0.0.1"), ReloadPolicy::ServFail);
+        let result = reloadable.reload(|| Err(ErrorCondition::InvalidLabel));
+
+        assert!(result.is_err());
+        assert!(!reloadable.is_reloading());
+        assert_eq!(
+            reloadable.answer(&query()).answers[0].rdata,
+            vec![10, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn test_parse_reload_policy() {
+        assert_eq!("serve-stale".parse(), Ok(ReloadPolicy::ServeStale));
+        assert_eq!("servfail".parse(), Ok(ReloadPolicy::ServFail));
+        assert!("sometimes".parse::<ReloadPolicy>().is_err());
+    }
+}
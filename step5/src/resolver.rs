@@ -0,0 +1,459 @@
+// src/resolver.rs
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::dns::{ErrorCondition, Header, RCODE_SERVER_FAILURE};
+
+/// The well-known DNS port, which outbound queries must not be sent from
+const DNS_PORT: u16 = 53;
+
+/// Largest datagram we take from an upstream. Queries are relayed with the client's OPT record,
+/// so answers can be as large as the client advertised, up to what UDP can carry.
+const MAX_UDP_RESPONSE: usize = 65535;
+
+/// How long a single upstream gets before the next one is tried
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The point in time by which a query has to be answered. It is created when the query arrives
+/// and handed down to every upstream attempt, so retries can never take longer than the client
+/// is willing to wait.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    pub fn after(duration: Duration) -> Deadline {
+        Deadline(Instant::now() + duration)
+    }
+
+    // Time left before the deadline, None once it has passed
+    pub fn remaining(&self) -> Option<Duration> {
+        self.0
+            .checked_duration_since(Instant::now())
+            .filter(|d| !d.is_zero())
+    }
+
+    pub fn has_passed(&self) -> bool {
+        self.remaining().is_none()
+    }
+}
+
+/// Forward a raw query to the upstreams in order until one of them answers. A truncated UDP
+/// answer is retried over TCP with the same upstream. Attempts that would start after the
+/// deadline are skipped.
+pub fn forward_with_deadline(
+    query: &[u8],
+    upstreams: &[SocketAddr],
+    deadline: Deadline,
+) -> Result<Vec<u8>, ErrorCondition> {
+    let mut last_error = ErrorCondition::DeadlineExceeded;
+
+    for upstream in upstreams {
+        let Some(remaining) = deadline.remaining() else {
+            break;
+        };
+
+        let response = match forward(query, *upstream, remaining.min(UPSTREAM_TIMEOUT)) {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("Upstream {} failed: {}", upstream, e);
+                last_error = e;
+                continue;
+            }
+        };
+
+        match Header::from_bytes(&response) {
+            Ok(header) if !header.tc => return Ok(response),
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Upstream {} sent a malformed response: {}", upstream, e);
+                last_error = e;
+                continue;
+            }
+        }
+
+        // The answer did not fit in a datagram, ask again over TCP if there is time left
+        let Some(remaining) = deadline.remaining() else {
+            break;
+        };
+        match forward_tcp(query, *upstream, remaining) {
+            Ok(response) => return Ok(response),
+            Err(e) => {
+                eprintln!("TCP retry to {} failed: {}", upstream, e);
+                last_error = e;
+            }
+        }
+    }
+
+    if deadline.has_passed() {
+        return Err(ErrorCondition::DeadlineExceeded);
+    }
+    Err(last_error)
+}
+
+/// Forward a query within the deadline, answering with SERVFAIL when no upstream made it in time.
+/// Returns None only when the query is too broken to even build a SERVFAIL for, one whose
+/// header or question section is cut off.
+pub fn forward_or_servfail(
+    query: &[u8],
+    upstreams: &[SocketAddr],
+    deadline: Deadline,
+) -> Option<Vec<u8>> {
+    match forward_with_deadline(query, upstreams, deadline) {
+        Ok(response) => Some(response),
+        Err(e) => {
+            eprintln!("Forward failed: {}", e);
+            servfail(query)
+        }
+    }
+}
+
+/// A SERVFAIL echoing the header and the question section of `query` as they were sent. Nothing
+/// in the query is parsed beyond that, so whatever the upstreams choked on can't stop the client
+/// from hearing about the failure.
+pub fn servfail(query: &[u8]) -> Option<Vec<u8>> {
+    let header = Header::from_bytes(query).ok()?;
+    let mut end = Header::DNS_HEADER_LEN;
+    for _ in 0..header.qdcount {
+        end = skip_name(query, end)? + 4;
+    }
+    let questions = query.get(Header::DNS_HEADER_LEN..end)?;
+
+    let mut response = Header {
+        qr: true,
+        aa: false,
+        tc: false,
+        ra: false,
+        z: 0,
+        rcode: RCODE_SERVER_FAILURE,
+        ancount: 0,
+        nscount: 0,
+        arcount: 0,
+        ..header
+    }
+    .to_bytes();
+    response.extend_from_slice(questions);
+    Some(response)
+}
+
+// The offset right after the name at `start`, which ends with the root label or a pointer
+fn skip_name(buf: &[u8], mut index: usize) -> Option<usize> {
+    loop {
+        let len = *buf.get(index)? as usize;
+        match len {
+            0 => return Some(index + 1),
+            len if len & 0b1100_0000 == 0b1100_0000 => return Some(index + 2),
+            len => index += 1 + len,
+        }
+    }
+}
+
+/// Send a raw query to one upstream over UDP and wait up to `timeout` for its answer. Each call
+/// uses a new socket on a random ephemeral port. Datagrams from other addresses or with a
+/// different transaction ID are ignored, so a stray packet on the socket is never relayed to the
+/// client.
+pub fn forward(
+    query: &[u8],
+    upstream: SocketAddr,
+    timeout: Duration,
+) -> Result<Vec<u8>, ErrorCondition> {
+    let id = Header::from_bytes(query)?.id;
+    let started = Instant::now();
+
+    let socket = outbound_socket(upstream)?;
+    socket.send_to(query, upstream)?;
+
+    let mut buf = vec![0; MAX_UDP_RESPONSE];
+    loop {
+        let remaining = timeout
+            .checked_sub(started.elapsed())
+            .filter(|d| !d.is_zero())
+            .ok_or_else(|| ErrorCondition::TimeoutErr(format!("no answer from {}", upstream)))?;
+        socket.set_read_timeout(Some(remaining))?;
+
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(v) => v,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(ErrorCondition::TimeoutErr(format!(
+                    "no answer from {}",
+                    upstream
+                )));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if from != upstream {
+            eprintln!("Ignoring datagram from unexpected source {}", from);
+            continue;
+        }
+        match Header::from_bytes(&buf[..len]) {
+            Ok(header) if header.qr && header.id == id => {
+                // A datagram filling the whole buffer may have been cut off, have it asked
+                // again over TCP like any other truncated answer
+                if len == buf.len() {
+                    buf[2] |= 0b0000_0010;
+                }
+                return Ok(buf[..len].to_vec());
+            }
+            _ => eprintln!(
+                "Ignoring datagram from {} that does not answer {:#06x}",
+                from, id
+            ),
+        }
+    }
+}
+
+// Every query gets a fresh socket on a port picked by the OS, so a spoofed answer has to guess
+// the source port as well as the transaction ID (RFC 5452 section 9.2)
+fn outbound_socket(upstream: SocketAddr) -> Result<UdpSocket, ErrorCondition> {
+    let bind_addr = if upstream.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind_addr)?;
+    if socket.local_addr()?.port() != DNS_PORT {
+        return Ok(socket);
+    }
+
+    // Never query from the well-known port. The first socket stays open until the second one
+    // is bound, so the OS can't hand out port 53 again.
+    Ok(UdpSocket::bind(bind_addr)?)
+}
+
+// DNS over TCP prefixes every message with its length as a 16 bit big-endian number
+fn forward_tcp(
+    query: &[u8],
+    upstream: SocketAddr,
+    timeout: Duration,
+) -> Result<Vec<u8>, ErrorCondition> {
+    let started = Instant::now();
+    let mut stream = TcpStream::connect_timeout(&upstream, timeout)?;
+    let timeout = timeout
+        .checked_sub(started.elapsed())
+        .filter(|d| !d.is_zero())
+        .ok_or(ErrorCondition::DeadlineExceeded)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let len = u16::try_from(query.len())
+        .map_err(|_| ErrorCondition::SerializationErr("Query too long for TCP".to_string()))?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(query)?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut response = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut response)?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    // Query for www.rust-trends.com, type A, class IN
+    const QUERY: [u8; 37] = [
+        0x43, 0xE6, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, b'w', b'w',
+        b'w', 0x0B, b'r', b'u', b's', b't', b'-', b't', b'r', b'e', b'n', b'd', b's', 0x03, b'c',
+        b'o', b'm', 0x00, 0x00, 0x01, 0x00, 0x01,
+    ];
+
+    // An upstream that receives queries but never answers them
+    fn silent_upstream() -> UdpSocket {
+        UdpSocket::bind("127.0.0.1:0").unwrap()
+    }
+
+    #[test]
+    fn test_forward_ignores_stray_packets() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = upstream.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (len, client) = upstream.recv_from(&mut buf).unwrap();
+            buf[2] |= 0b1000_0000;
+
+            // Right ID but from somebody else
+            let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+            stranger.send_to(&buf[..len], client).unwrap();
+
+            // Right source but another transaction
+            let mut other = buf;
+            other[0] ^= 0xFF;
+            upstream.send_to(&other[..len], client).unwrap();
+
+            // The real answer, marked with an answer count of 1
+            buf[7] = 1;
+            upstream.send_to(&buf[..len], client).unwrap();
+        });
+
+        let response = forward(&QUERY, addr, Duration::from_secs(2)).unwrap();
+        let header = Header::from_bytes(&response).unwrap();
+        assert_eq!(header.id, 0x43E6);
+        assert_eq!(header.ancount, 1);
+    }
+
+    #[test]
+    fn test_forward_uses_ephemeral_source_ports() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = upstream.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut ports = Vec::new();
+            let mut buf = [0; 512];
+            for _ in 0..5 {
+                let (len, client) = upstream.recv_from(&mut buf).unwrap();
+                ports.push(client.port());
+                buf[2] |= 0b1000_0000;
+                upstream.send_to(&buf[..len], client).unwrap();
+            }
+            ports
+        });
+
+        for _ in 0..5 {
+            forward(&QUERY, addr, Duration::from_secs(2)).unwrap();
+        }
+
+        let ports = server.join().unwrap();
+        assert!(ports.iter().all(|&port| port != 0 && port != DNS_PORT));
+        // The OS picks ports at random, five queries in a row all getting the same is unlikely
+        assert!(ports.iter().any(|&port| port != ports[0]), "{:?}", ports);
+    }
+
+    #[test]
+    fn test_forward_relays_answers_larger_than_512_bytes() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = upstream.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (len, client) = upstream.recv_from(&mut buf).unwrap();
+            // The question followed by padding, as if the answers of an EDNS query were there
+            let mut response = buf[..len].to_vec();
+            response[2] |= 0b1000_0000;
+            response.resize(1500, 0);
+            upstream.send_to(&response, client).unwrap();
+        });
+
+        let response = forward(&QUERY, addr, Duration::from_secs(2)).unwrap();
+        assert_eq!(response.len(), 1500);
+        assert!(!Header::from_bytes(&response).unwrap().tc);
+    }
+
+    #[test]
+    fn test_forward_timeout() {
+        let upstream = silent_upstream();
+        let result = forward(
+            &QUERY,
+            upstream.local_addr().unwrap(),
+            Duration::from_millis(100),
+        );
+        assert!(matches!(result, Err(ErrorCondition::TimeoutErr(_))));
+    }
+
+    #[test]
+    fn test_deadline_remaining() {
+        let deadline = Deadline::after(Duration::from_secs(60));
+        assert!(deadline.remaining().unwrap() > Duration::from_secs(59));
+        assert!(!deadline.has_passed());
+
+        let deadline = Deadline::after(Duration::ZERO);
+        assert!(deadline.remaining().is_none());
+        assert!(deadline.has_passed());
+    }
+
+    #[test]
+    fn test_slow_upstreams_give_servfail_within_deadline() {
+        let slow = [silent_upstream(), silent_upstream()];
+        let upstreams: Vec<SocketAddr> = slow.iter().map(|s| s.local_addr().unwrap()).collect();
+
+        let started = Instant::now();
+        let response = forward_or_servfail(
+            &QUERY,
+            &upstreams,
+            Deadline::after(Duration::from_millis(300)),
+        )
+        .unwrap();
+        let elapsed = started.elapsed();
+
+        assert!(elapsed >= Duration::from_millis(300));
+        assert!(elapsed < Duration::from_millis(600), "took {:?}", elapsed);
+
+        let header = Header::from_bytes(&response).unwrap();
+        assert_eq!(header.id, 0x43E6);
+        assert!(header.qr);
+        assert_eq!(header.rcode, RCODE_SERVER_FAILURE);
+    }
+
+    #[test]
+    fn test_servfail_for_query_that_does_not_parse() {
+        // A type and class we know nothing about, and an additional record that isn't there
+        let mut query = QUERY.to_vec();
+        query[11] = 1;
+        query[33..37].copy_from_slice(&[0xFF, 0x00, 0x12, 0x34]);
+        assert!(crate::dns::Message::from_bytes(&query).is_err());
+
+        let upstream = silent_upstream();
+        let response = forward_or_servfail(
+            &query,
+            &[upstream.local_addr().unwrap()],
+            Deadline::after(Duration::from_millis(100)),
+        )
+        .unwrap();
+
+        let header = Header::from_bytes(&response).unwrap();
+        assert_eq!(header.id, 0x43E6);
+        assert!(header.qr && header.rd);
+        assert_eq!(header.rcode, RCODE_SERVER_FAILURE);
+        assert_eq!((header.qdcount, header.arcount), (1, 0));
+        assert_eq!(&response[12..], &query[12..]);
+
+        // Without a complete question there is nothing to answer
+        assert!(servfail(&query[..30]).is_none());
+    }
+
+    #[test]
+    fn test_retry_after_deadline_is_skipped() {
+        let first = silent_upstream();
+        let second = silent_upstream();
+        let upstreams = [first.local_addr().unwrap(), second.local_addr().unwrap()];
+
+        let result = forward_with_deadline(
+            &QUERY,
+            &upstreams,
+            Deadline::after(Duration::from_millis(200)),
+        );
+        assert!(matches!(result, Err(ErrorCondition::DeadlineExceeded)));
+
+        // The first upstream used up the whole budget, so the second was never asked
+        let mut buf = [0; 512];
+        assert!(first.recv_from(&mut buf).is_ok());
+        second.set_nonblocking(true).unwrap();
+        assert!(second.recv_from(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_tcp_retry_respects_deadline() {
+        // Answers over UDP with the TC bit set, then accepts the TCP retry but never responds
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(addr).unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (len, src) = udp.recv_from(&mut buf).unwrap();
+            buf[2] |= 0b1000_0010;
+            udp.send_to(&buf[..len], src).unwrap();
+            let _connection = tcp.accept().unwrap();
+            std::thread::sleep(Duration::from_secs(2));
+        });
+
+        let started = Instant::now();
+        let response =
+            forward_or_servfail(&QUERY, &[addr], Deadline::after(Duration::from_millis(300)))
+                .unwrap();
+        assert!(started.elapsed() < Duration::from_millis(600));
+        assert_eq!(
+            Header::from_bytes(&response).unwrap().rcode,
+            RCODE_SERVER_FAILURE
+        );
+    }
+}
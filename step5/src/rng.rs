@@ -0,0 +1,41 @@
+// src/rng.rs
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// A source of random numbers, so code that makes random choices can be tested with a
+/// generator that always produces the same sequence
+pub trait Rng {
+    fn next_u32(&mut self) -> u32;
+
+    // A number in 0..=max, without the bias of taking the remainder
+    fn up_to(&mut self, max: u32) -> u32 {
+        ((self.next_u32() as u64 * (max as u64 + 1)) >> 32) as u32
+    }
+}
+
+/// The xorshift64 generator. Fast and good enough for load balancing and IDs, not for keys.
+#[derive(Debug, Clone)]
+pub struct XorShift(u64);
+
+impl XorShift {
+    /// A generator that produces the same sequence for the same seed
+    pub fn new(seed: u64) -> Self {
+        // Zero is the one state xorshift never leaves
+        XorShift(seed.max(1))
+    }
+
+    /// A generator with an unpredictable seed. std has no random number generator, but every
+    /// RandomState is seeded with fresh random keys, so hashing nothing with one gives random bits.
+    pub fn from_entropy() -> Self {
+        XorShift::new(RandomState::new().build_hasher().finish())
+    }
+}
+
+impl Rng for XorShift {
+    fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 32) as u32
+    }
+}
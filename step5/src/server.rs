@@ -0,0 +1,715 @@
+// src/server.rs
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::cache::Cache;
+use crate::dns::{
+    Class, Header, Message, Name, ResourceRecord, Type, MAX_DNS_MESSAGE_SIZE, RCODE_NAME_ERROR,
+    RCODE_REFUSED, RCODE_SERVER_FAILURE,
+};
+use crate::edns::EdnsContext;
+use crate::guard::{PenaltyList, QrPolicy};
+use crate::rdata::RData;
+use crate::reload::SharedSource;
+use crate::resolver::{forward_or_servfail, forward_with_deadline, servfail, Deadline};
+
+/// Time a client is given to get an answer before we give up with SERVFAIL
+const QUERY_DEADLINE: Duration = Duration::from_secs(2);
+
+/// What a CHAOS TXT query for version.bind is answered with
+const VERSION: &str = concat!("dns-server ", env!("CARGO_PKG_VERSION"));
+
+/// Answers queries from the local zone and forwards what the zone does not know to the
+/// upstream resolvers, caching their answers. Shared by the UDP and TCP listeners.
+pub struct Server {
+    zone: Option<SharedSource>,
+    upstreams: Vec<SocketAddr>,
+    cache: Cache,
+    nsid: Option<Vec<u8>>,
+    qr_policy: QrPolicy,
+    penalties: PenaltyList,
+    classes: Vec<Class>,
+}
+
+impl Server {
+    pub fn new(zone: Option<SharedSource>, upstreams: Vec<SocketAddr>) -> Self {
+        Server {
+            zone,
+            upstreams,
+            cache: Cache::new(),
+            nsid: None,
+            qr_policy: QrPolicy::default(),
+            penalties: PenaltyList::new(),
+            classes: vec![Class::IN],
+        }
+    }
+
+    /// Identify this instance with `nsid` to clients that ask for it with the EDNS NSID option
+    /// (RFC 5001), e.g. to tell anycast nodes apart
+    pub fn with_nsid(mut self, nsid: Vec<u8>) -> Self {
+        self.nsid = Some(nsid);
+        self
+    }
+
+    /// Answer queries in these classes, queries in any other class are refused. Only IN is
+    /// served by default, the zone and the upstreams have nothing to say about the others.
+    pub fn with_classes(mut self, classes: Vec<Class>) -> Self {
+        self.classes = classes;
+        self
+    }
+
+    /// Choose what happens to sources that send us responses instead of queries
+    pub fn with_qr_policy(mut self, policy: QrPolicy) -> Self {
+        self.qr_policy = policy;
+        self
+    }
+
+    /// Answer a raw query as received over UDP from `source`. Zone and cached answers that don't
+    /// fit in a datagram are truncated with the TC bit set, fresh answers from an upstream are
+    /// relayed exactly as it sent them. Returns None when there is nothing sensible to send
+    /// back, which includes responses sent to us and anything from a penalized source.
+    pub fn handle(&self, query: &[u8], source: SocketAddr) -> Option<Vec<u8>> {
+        // Whatever the opcode, answering a response would be replying to a reply, which can loop
+        // between two servers or bounce traffic at whoever the source was spoofed to be
+        let header = Header::from_bytes(query).ok()?;
+        if header.qr {
+            self.reject_response(&header, source);
+            return None;
+        }
+        if self.penalties.is_penalized(source.ip()) {
+            return None;
+        }
+
+        let parsed = match Message::from_bytes(query) {
+            Ok(parsed) => parsed,
+            // Maybe an upstream makes more sense of it, it is relayed without caching
+            Err(e) => {
+                eprintln!("Failed to parse query: {}", e);
+                if self.upstreams.is_empty() {
+                    return None;
+                }
+                let deadline = Deadline::after(QUERY_DEADLINE);
+                return forward_or_servfail(query, &self.upstreams, deadline);
+            }
+        };
+
+        log_questions(&parsed);
+        if let Some(response) = self.local_response(&parsed) {
+            return self.to_datagram(&parsed, response);
+        }
+        if self.upstreams.is_empty() {
+            return None;
+        }
+
+        match self.forward(&parsed, query) {
+            Some(Forwarded::Relayed(response)) => {
+                print_answers(&response);
+                Some(response)
+            }
+            Some(Forwarded::Cached(cached)) => {
+                self.to_datagram(&parsed, cached_answer(&parsed, cached))
+            }
+            None => servfail(query),
+        }
+    }
+
+    /// Answer a parsed query, as used by the TCP listener
+    pub fn respond(&self, query: &Message) -> Message {
+        log_questions(query);
+        let edns = EdnsContext::from_query(query);
+
+        if let Some(mut response) = self.local_response(query) {
+            self.add_edns(edns.as_ref(), &mut response);
+            return response;
+        }
+
+        let forwarded = query
+            .to_bytes()
+            .ok()
+            .and_then(|bytes| self.forward(query, &bytes));
+        match forwarded {
+            Some(Forwarded::Relayed(bytes)) => match Message::from_bytes(&bytes) {
+                Ok(mut response) => {
+                    // Whatever the upstream did, a client that didn't use EDNS can't handle OPT
+                    if edns.is_none() {
+                        response.edns = None;
+                    }
+                    response
+                }
+                Err(e) => {
+                    eprintln!("Failed to parse upstream response: {}", e);
+                    self.servfail(query, edns.as_ref())
+                }
+            },
+            Some(Forwarded::Cached(cached)) => {
+                let mut response = cached_answer(query, cached);
+                self.add_edns(edns.as_ref(), &mut response);
+                response
+            }
+            None => self.servfail(query, edns.as_ref()),
+        }
+    }
+
+    // Ask the upstreams about a query the zone can't answer, unless one of them was asked the
+    // same question recently and its answer is still cached. `raw` is the query as the client
+    // sent it, which is what goes upstream. None when no upstream answered in time.
+    fn forward(&self, query: &Message, raw: &[u8]) -> Option<Forwarded> {
+        let deadline = Deadline::after(QUERY_DEADLINE);
+        let [question] = &query.questions[..] else {
+            return match forward_with_deadline(raw, &self.upstreams, deadline) {
+                Ok(response) => Some(Forwarded::Relayed(response)),
+                Err(e) => {
+                    eprintln!("Forward failed: {}", e);
+                    None
+                }
+            };
+        };
+
+        let mut relayed = None;
+        let cached = self.cache.get_or_forward(question, |_| {
+            let response = forward_with_deadline(raw, &self.upstreams, deadline)?;
+            let parsed = Message::from_bytes(&response);
+            relayed = Some(response);
+            parsed
+        });
+        match (relayed, cached) {
+            (Some(response), _) => Some(Forwarded::Relayed(response)),
+            (None, Ok(cached)) => Some(Forwarded::Cached(cached)),
+            (None, Err(e)) => {
+                eprintln!("Forward failed: {}", e);
+                None
+            }
+        }
+    }
+
+    // Serialize a response to a query received over UDP, with an OPT record if the query had one
+    fn to_datagram(&self, query: &Message, mut response: Message) -> Option<Vec<u8>> {
+        let edns = EdnsContext::from_query(query);
+        self.add_edns(edns.as_ref(), &mut response);
+
+        println!(
+            "Response: {} answer(s), rcode={}",
+            response.answers.len(),
+            response.header.rcode
+        );
+
+        // EDNS clients tell us how much they can take, everyone else gets at most 512 bytes
+        let max_size = edns
+            .as_ref()
+            .map_or(MAX_DNS_MESSAGE_SIZE, EdnsContext::max_response_size);
+        match response.to_bytes_truncated(max_size) {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                eprintln!("Failed to serialize response: {}", e);
+                None
+            }
+        }
+    }
+
+    // The response we give without the upstreams: refused for a class we don't serve, the
+    // version for version.bind, or what the zone has to say
+    fn local_response(&self, query: &Message) -> Option<Message> {
+        // Asked by tools like `dig CH TXT version.bind`, whether or not CHAOS is served
+        if let [question] = &query.questions[..] {
+            let version_bind = Name::parse("version.bind").ok()?;
+            if question.qclass == Class::CH
+                && question.qtype == Type::TXT
+                && question.name.eq_ignore_case(&version_bind)
+            {
+                return version_response(query);
+            }
+        }
+
+        if let Some(question) = query
+            .questions
+            .iter()
+            .find(|question| !self.classes.contains(&question.qclass))
+        {
+            println!("Refusing query in class {:?}", question.qclass);
+            let mut response = query.response();
+            response.header.rcode = RCODE_REFUSED;
+            return Some(response);
+        }
+
+        let response = self.zone.as_ref()?.answer(query);
+        (!self.forwards(&response)).then_some(response)
+    }
+
+    // A UDP source address is easily spoofed, so a response is never answered and the source
+    // is only punished by ignoring it for a while
+    fn reject_response(&self, header: &Header, source: SocketAddr) {
+        match self.qr_policy {
+            QrPolicy::Drop => {}
+            QrPolicy::Log => eprintln!("Dropped response from {} (ID: {:#06x})", source, header.id),
+            QrPolicy::Penalize => {
+                let offenses = self.penalties.penalize(source.ip());
+                eprintln!(
+                    "Dropped response from {} (ID: {:#06x}), ignoring it ({} offense(s))",
+                    source, header.id, offenses
+                );
+            }
+        }
+    }
+
+    // Names the zone does not know or has no authority over are asked upstream, if there is an
+    // upstream to ask
+    fn forwards(&self, zone_response: &Message) -> bool {
+        !self.upstreams.is_empty()
+            && matches!(zone_response.header.rcode, RCODE_NAME_ERROR | RCODE_REFUSED)
+    }
+
+    fn servfail(&self, query: &Message, edns: Option<&EdnsContext>) -> Message {
+        let mut response = query.response();
+        response.header.rcode = RCODE_SERVER_FAILURE;
+        self.add_edns(edns, &mut response);
+        response
+    }
+
+    // A query with an OPT record gets one back (RFC 6891 section 7), a query without never does.
+    // The NSID is only included when the query asked for it.
+    fn add_edns(&self, edns: Option<&EdnsContext>, response: &mut Message) {
+        response.edns = edns.map(|edns| edns.response_edns(self.nsid.as_deref()));
+    }
+}
+
+// An answer from an upstream, either just received or taken from the cache
+enum Forwarded {
+    Relayed(Vec<u8>),
+    Cached(Message),
+}
+
+// A cached answer made into the answer to `query`. It was cached for another client, so the ID
+// and the question, which may differ in case, are taken from the query.
+fn cached_answer(query: &Message, cached: Message) -> Message {
+    Message {
+        header: Header {
+            id: query.header.id,
+            rd: query.header.rd,
+            ..cached.header
+        },
+        questions: query.questions.clone(),
+        ..cached
+    }
+}
+
+// The answer to a CHAOS TXT query for version.bind: a TXT record with our name and version
+fn version_response(query: &Message) -> Option<Message> {
+    let question = query.questions.first()?;
+    let mut rdata = vec![VERSION.len() as u8];
+    rdata.extend_from_slice(VERSION.as_bytes());
+    let record = ResourceRecord::new(question.name.clone(), Type::TXT, Class::CH, 0, rdata).ok()?;
+
+    let mut response = query.response();
+    response.header.aa = true;
+    response.answers.push(record);
+    Some(response)
+}
+
+fn log_questions(query: &Message) {
+    for question in &query.questions {
+        println!(
+            "Question: {} ({:?}, {:?})",
+            question.name_to_string(),
+            question.qtype,
+            question.qclass
+        );
+    }
+}
+
+fn print_answers(response: &[u8]) {
+    let Ok(resp_header) = Header::from_bytes(response) else {
+        return;
+    };
+    println!(
+        "Response: {} answer(s), rcode={}",
+        resp_header.ancount, resp_header.rcode
+    );
+
+    // Skip header and question section to reach the answer section
+    let mut offset = 12;
+    for _ in 0..resp_header.qdcount {
+        while offset < response.len() {
+            let byte = response[offset];
+            if byte == 0 {
+                offset += 1;
+                break;
+            } else if byte & 0b11000000 == 0b11000000 {
+                offset += 2;
+                break;
+            } else {
+                offset += 1 + byte as usize;
+            }
+        }
+        offset += 4; // skip QTYPE + QCLASS
+    }
+
+    for i in 0..resp_header.ancount {
+        match ResourceRecord::from_bytes(response, offset) {
+            Ok((record, next_offset)) => {
+                // Names in the RDATA are already spelled out, it parses on its own
+                let rdata = match RData::parse(&record.rtype, &record.rdata, 0, record.rdlength) {
+                    // Types without their own variant may still point to a name worth showing
+                    Ok(RData::Raw(data)) => match record.target_name(response) {
+                        Some(target) => format!("{}.", target),
+                        None => RData::Raw(data).to_string(),
+                    },
+                    Ok(rdata) => rdata.to_string(),
+                    Err(e) => format!("<{}>", e),
+                };
+                println!(
+                    "  Answer {}: {}. {:?} {}",
+                    i + 1,
+                    record.name,
+                    record.rtype,
+                    rdata
+                );
+                offset = next_offset;
+            }
+            Err(e) => {
+                eprintln!("  Failed to parse answer {}: {}", i + 1, e);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Class, Name, Question, Type, RCODE_NO_ERROR};
+    use crate::edns::{Edns, EdnsOption};
+    use crate::zone::Zone;
+    use std::sync::Arc;
+
+    const CLIENT: &str = "192.0.2.1:5353";
+
+    fn server() -> Server {
+        let zone = Zone::parse("$ORIGIN example.com\nwww.example.com A 10.0.0.1 60").unwrap();
+        Server::new(Some(Arc::new(zone)), Vec::new())
+    }
+
+    fn query(name: &str) -> Message {
+        Message::query(name, Type::A).unwrap()
+    }
+
+    #[test]
+    fn test_udp_and_tcp_paths_agree() {
+        let server = server();
+
+        for (name, rcode, answers) in [
+            ("www.example.com", RCODE_NO_ERROR, 1),
+            ("missing.example.com", RCODE_NAME_ERROR, 0),
+        ] {
+            let query = query(name);
+            let parsed = server.respond(&query);
+            let raw = server
+                .handle(&query.to_bytes().unwrap(), CLIENT.parse().unwrap())
+                .unwrap();
+
+            assert_eq!(parsed.to_bytes().unwrap(), raw);
+            assert_eq!(parsed.header.rcode, rcode);
+            assert_eq!(parsed.answers.len(), answers);
+        }
+    }
+
+    #[test]
+    fn test_nsid_only_when_requested() {
+        let server = server().with_nsid(b"ns1.ams".to_vec());
+        let response = |edns: Option<Edns>| {
+            let mut query = query("www.example.com");
+            query.edns = edns;
+            let raw = server
+                .handle(&query.to_bytes().unwrap(), CLIENT.parse().unwrap())
+                .unwrap();
+            Message::from_bytes(&raw).unwrap().edns
+        };
+
+        let mut asks = Edns::new(1232);
+        asks.options.push(EdnsOption::Nsid(Vec::new()));
+        let edns = response(Some(asks)).unwrap();
+        assert_eq!(edns.options, vec![EdnsOption::Nsid(b"ns1.ams".to_vec())]);
+
+        // EDNS without the NSID option still gets an OPT record back, but no identifier
+        let edns = response(Some(Edns::new(1232))).unwrap();
+        assert!(edns.options.is_empty());
+
+        assert!(response(None).is_none());
+    }
+
+    #[test]
+    fn test_opt_record_only_for_edns_queries() {
+        // 40 addresses don't fit in 512 bytes, but do fit in what EDNS clients accept
+        let records: Vec<_> = (1..=40)
+            .map(|i| format!("www.example.com A 10.0.0.{} 60", i))
+            .collect();
+        let zone = Zone::parse(&records.join("\n")).unwrap();
+        let server = Server::new(Some(Arc::new(zone)), Vec::new());
+        let source = CLIENT.parse().unwrap();
+
+        let plain = query("www.example.com");
+        let raw = server.handle(&plain.to_bytes().unwrap(), source).unwrap();
+        let response = Message::from_bytes(&raw).unwrap();
+        assert!(response.edns.is_none());
+        assert!(response.header.tc);
+        assert!(raw.len() <= MAX_DNS_MESSAGE_SIZE);
+        assert!(server.respond(&plain).edns.is_none());
+
+        let mut with_edns = plain.clone();
+        with_edns.edns = Some(Edns::new(1232));
+        let raw = server
+            .handle(&with_edns.to_bytes().unwrap(), source)
+            .unwrap();
+        let response = Message::from_bytes(&raw).unwrap();
+        assert!(response.edns.is_some());
+        assert!(!response.header.tc);
+        assert_eq!(response.answers.len(), 40);
+        assert!(server.respond(&with_edns).edns.is_some());
+    }
+
+    fn query_in_class(name: &str, qtype: Type, qclass: Class) -> Message {
+        Message::query_for(Question::new(Name::parse(name).unwrap(), qtype, qclass).unwrap())
+    }
+
+    #[test]
+    fn test_unserved_class_is_refused() {
+        let hesiod = query_in_class("www.example.com", Type::A, Class::HS);
+
+        let response = server().respond(&hesiod);
+        assert_eq!(response.header.rcode, RCODE_REFUSED);
+        assert!(response.answers.is_empty());
+
+        let raw = server()
+            .handle(&hesiod.to_bytes().unwrap(), CLIENT.parse().unwrap())
+            .unwrap();
+        assert_eq!(Header::from_bytes(&raw).unwrap().rcode, RCODE_REFUSED);
+
+        // Unless the class is enabled
+        let server = server().with_classes(vec![Class::IN, Class::HS]);
+        assert_eq!(server.respond(&hesiod).header.rcode, RCODE_NO_ERROR);
+    }
+
+    #[test]
+    fn test_any_and_unknown_classes_are_refused() {
+        for qclass in [Class::_ALL_, Class::Other(254), Class::Other(1234)] {
+            let query = query_in_class("www.example.com", Type::A, qclass.clone());
+            let bytes = query.to_bytes().unwrap();
+            assert_eq!(Message::from_bytes(&bytes).unwrap(), query);
+
+            let raw = server().handle(&bytes, CLIENT.parse().unwrap()).unwrap();
+            let response = Message::from_bytes(&raw).unwrap();
+            assert_eq!(response.header.rcode, RCODE_REFUSED, "{:?}", qclass);
+            assert_eq!(response.questions[0].qclass, qclass);
+        }
+    }
+
+    #[test]
+    fn test_version_bind_is_answered() {
+        let response = server().respond(&query_in_class("VERSION.bind", Type::TXT, Class::CH));
+        assert_eq!(response.header.rcode, RCODE_NO_ERROR);
+        assert_eq!(response.answers[0].rclass, Class::CH);
+        assert_eq!(&response.answers[0].rdata[1..], VERSION.as_bytes());
+
+        // Any other CHAOS query is in a class we don't serve
+        let response = server().respond(&query_in_class("hostname.bind", Type::TXT, Class::CH));
+        assert_eq!(response.header.rcode, RCODE_REFUSED);
+    }
+
+    #[test]
+    fn test_short_datagram_is_rejected() {
+        let server = server();
+        let source = CLIENT.parse().unwrap();
+
+        // Shorter than a header
+        assert!(server
+            .handle(&[0x43, 0xE6, 1, 0, 0, 1, 0, 0, 0, 0], source)
+            .is_none());
+        // A header announcing a question that isn't there
+        let header = [0x43, 0xE6, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        assert!(server.handle(&header, source).is_none());
+        assert!(Message::from_bytes(&header).is_err());
+    }
+
+    // An upstream that answers one query by sending it back as a response, and hands over
+    // the query it got
+    fn echoing_upstream() -> (SocketAddr, std::thread::JoinHandle<Vec<u8>>) {
+        let upstream = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = upstream.local_addr().unwrap();
+        let received = std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (len, client) = upstream.recv_from(&mut buf).unwrap();
+            let query = buf[..len].to_vec();
+            buf[2] |= 0b1000_0000;
+            upstream.send_to(&buf[..len], client).unwrap();
+            query
+        });
+        (addr, received)
+    }
+
+    #[test]
+    fn test_forwarded_unknown_types_and_glue_survive_tcp_path() {
+        let upstream = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = upstream.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (len, client) = upstream.recv_from(&mut buf).unwrap();
+            let query = Message::from_bytes(&buf[..len]).unwrap();
+            let name = query.questions[0].name.clone();
+            let mut response = query.response();
+            // An RRSIG, a type the server has no variant for, and glue for a name server
+            let rrsig = ResourceRecord::new(name, Type::Unknown(46), Class::IN, 60, vec![0; 20]);
+            response.answers.push(rrsig.unwrap());
+            let glue = ResourceRecord::new(
+                Name::parse("ns1.example.org").unwrap(),
+                Type::A,
+                Class::IN,
+                60,
+                vec![10, 0, 0, 53],
+            );
+            response.additionals.push(glue.unwrap());
+            let bytes = response.to_bytes().unwrap();
+            upstream.send_to(&bytes, client).unwrap();
+        });
+
+        let server = Server::new(None, vec![addr]);
+        let query = Message::query("www.example.org", Type::Unknown(65)).unwrap();
+        let response = server.respond(&query);
+        assert_eq!(response.header.rcode, RCODE_NO_ERROR);
+        assert_eq!(response.answers[0].rtype, Type::Unknown(46));
+        assert_eq!(response.additionals.len(), 1);
+        assert_eq!(response.additionals[0].rdata, vec![10, 0, 0, 53]);
+    }
+
+    #[test]
+    fn test_forwarded_answer_is_cached_for_the_next_client() {
+        // Answers a single query, anything after it would time out
+        let upstream = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = upstream.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (len, client) = upstream.recv_from(&mut buf).unwrap();
+            let query = Message::from_bytes(&buf[..len]).unwrap();
+            let mut response = query.response();
+            let name = query.questions[0].name.clone();
+            let record = ResourceRecord::new(name, Type::A, Class::IN, 300, vec![10, 0, 0, 7]);
+            response.answers.push(record.unwrap());
+            upstream
+                .send_to(&response.to_bytes().unwrap(), client)
+                .unwrap();
+        });
+        let server = Server::new(None, vec![addr]);
+        let source = CLIENT.parse().unwrap();
+
+        let first = query("www.example.org");
+        let raw = server.handle(&first.to_bytes().unwrap(), source).unwrap();
+        assert_eq!(Header::from_bytes(&raw).unwrap().id, first.header.id);
+
+        let mut second = query("WWW.Example.org");
+        second.header.id = first.header.id.wrapping_add(1);
+        let raw = server.handle(&second.to_bytes().unwrap(), source).unwrap();
+        let response = Message::from_bytes(&raw).unwrap();
+        assert_eq!(response.header.id, second.header.id);
+        assert_eq!(response.questions, second.questions);
+        assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 7]);
+
+        let third = query("www.example.org");
+        let response = server.respond(&third);
+        assert_eq!(response.header.id, third.header.id);
+        assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 7]);
+    }
+
+    fn query_with_options(name: &str, options: Vec<EdnsOption>) -> Message {
+        let mut edns = Edns::new(1232);
+        edns.options = options;
+        let mut query = query(name);
+        query.edns = Some(edns);
+        query
+    }
+
+    #[test]
+    fn test_unknown_option_relayed_unchanged() {
+        let options = vec![
+            EdnsOption::Unknown {
+                code: 65001,
+                data: b"opaque".to_vec(),
+            },
+            EdnsOption::Nsid(Vec::new()),
+        ];
+        let query = query_with_options("www.example.org", options.clone());
+        let bytes = query.to_bytes().unwrap();
+
+        // Over UDP the query is relayed byte for byte
+        let (upstream, received) = echoing_upstream();
+        let server = Server::new(None, vec![upstream]);
+        let response = server.handle(&bytes, CLIENT.parse().unwrap()).unwrap();
+        assert_eq!(received.join().unwrap(), bytes);
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(response.edns.unwrap().options, options);
+
+        // Over TCP it is parsed first, which must not lose or reorder the options
+        let (upstream, received) = echoing_upstream();
+        let server = Server::new(None, vec![upstream]);
+        let response = server.respond(&query);
+        assert_eq!(received.join().unwrap(), bytes);
+        assert_eq!(response.edns.unwrap().options, options);
+    }
+
+    #[test]
+    fn test_unknown_option_ignored_by_zone() {
+        let options = vec![EdnsOption::Unknown {
+            code: 65001,
+            data: b"opaque".to_vec(),
+        }];
+        let query = query_with_options("www.example.com", options);
+        let response = server().respond(&query);
+
+        assert_eq!(response.header.rcode, RCODE_NO_ERROR);
+        assert_eq!(response.answers.len(), 1);
+        assert!(response.edns.unwrap().options.is_empty());
+    }
+
+    #[test]
+    fn test_response_penalizes_its_source() {
+        let server = server().with_qr_policy(QrPolicy::Penalize);
+        let attacker: SocketAddr = CLIENT.parse().unwrap();
+        let bystander: SocketAddr = "192.0.2.2:5353".parse().unwrap();
+
+        let query = query("www.example.com").to_bytes().unwrap();
+        let mut response = query.clone();
+        response[2] |= 0b1000_0000;
+
+        assert!(server.handle(&response, attacker).is_none());
+        assert_eq!(server.penalties.offenses(attacker.ip()), 1);
+        assert!(server.handle(&response, attacker).is_none());
+        assert_eq!(server.penalties.offenses(attacker.ip()), 2);
+
+        // Even real queries from the source go unanswered now, others are still served
+        assert!(server.handle(&query, attacker).is_none());
+        assert!(server.handle(&query, bystander).is_some());
+        assert_eq!(server.penalties.offenses(bystander.ip()), 0);
+    }
+
+    #[test]
+    fn test_response_with_other_opcode_is_dropped() {
+        let server = server().with_qr_policy(QrPolicy::Penalize);
+        let source: SocketAddr = CLIENT.parse().unwrap();
+
+        // A NOTIFY (4) and a STATUS (2) response would otherwise get NOTIMP back
+        for opcode in [4, 2] {
+            let mut response = query("www.example.com").to_bytes().unwrap();
+            response[2] = 0b1000_0000 | opcode << 3;
+            assert!(server.handle(&response, source).is_none());
+        }
+        assert_eq!(server.penalties.offenses(source.ip()), 2);
+    }
+
+    #[test]
+    fn test_response_only_logged_by_default() {
+        let server = server();
+        let source: SocketAddr = CLIENT.parse().unwrap();
+        let mut response = query("www.example.com").to_bytes().unwrap();
+        response[2] |= 0b1000_0000;
+
+        assert!(server.handle(&response, source).is_none());
+        assert_eq!(server.penalties.offenses(source.ip()), 0);
+    }
+}
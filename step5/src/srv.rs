@@ -0,0 +1,131 @@
+// src/srv.rs
+use crate::dns::{ErrorCondition, Name};
+use crate::rng::Rng;
+
+/// The RDATA of an SRV record (RFC 2782): where a service runs and how to choose among servers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Srv {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: Name,
+}
+
+impl Srv {
+    // Decode SRV RDATA, `packet` is the message it came from for following compression pointers
+    pub fn from_rdata(rdata: &[u8], packet: &[u8]) -> Result<Srv, ErrorCondition> {
+        let fields = rdata.get(..6).ok_or_else(|| {
+            ErrorCondition::DeserializationErr(format!("SRV RDATA of {} bytes", rdata.len()))
+        })?;
+
+        Ok(Srv {
+            priority: u16::from_be_bytes([fields[0], fields[1]]),
+            weight: u16::from_be_bytes([fields[2], fields[3]]),
+            port: u16::from_be_bytes([fields[4], fields[5]]),
+            target: Name::from_rdata(&rdata[6..], packet)?,
+        })
+    }
+}
+
+/// Pick the server to contact first with the selection algorithm of RFC 2782: only records
+/// with the lowest priority take part, and each of them is chosen with a chance proportional
+/// to its weight. Records with weight 0 keep a small chance of being picked, and when all
+/// weights are 0 every record is equally likely. Returns None when there are no records.
+pub fn select_srv_target<'a, R: Rng>(records: &'a [Srv], rng: &mut R) -> Option<&'a Srv> {
+    let priority = records.iter().map(|srv| srv.priority).min()?;
+
+    // The RFC puts weight 0 records first, so they are only hit when the random number is 0
+    let mut candidates: Vec<&Srv> = records
+        .iter()
+        .filter(|srv| srv.priority == priority)
+        .collect();
+    candidates.sort_by_key(|srv| srv.weight != 0);
+
+    let total: u32 = candidates.iter().map(|srv| srv.weight as u32).sum();
+    if total == 0 {
+        let i = rng.up_to(candidates.len() as u32 - 1) as usize;
+        return Some(candidates[i]);
+    }
+
+    let pick = rng.up_to(total);
+    let mut running = 0;
+    candidates.into_iter().find(|srv| {
+        running += srv.weight as u32;
+        running >= pick
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::XorShift;
+
+    fn srv(priority: u16, weight: u16, target: &str) -> Srv {
+        Srv {
+            priority,
+            weight,
+            port: 5060,
+            target: Name::parse(target).unwrap(),
+        }
+    }
+
+    // How often each target is selected in `samples` draws from a generator seeded with 42
+    fn counts(records: &[Srv], samples: usize) -> Vec<usize> {
+        let mut rng = XorShift::new(42);
+        let mut counts = vec![0; records.len()];
+        for _ in 0..samples {
+            let selected = select_srv_target(records, &mut rng).unwrap();
+            counts[records.iter().position(|srv| srv == selected).unwrap()] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn test_weighted_selection_is_deterministic_and_proportional() {
+        let records = [
+            srv(10, 10, "a.example.com"),
+            srv(10, 30, "b.example.com"),
+            srv(10, 60, "c.example.com"),
+            srv(20, 1000, "backup.example.com"),
+        ];
+
+        let counts = counts(&records, 10_000);
+        assert_eq!(counts, self::counts(&records, 10_000));
+
+        // The backup has a lower priority and is never chosen while the others exist
+        assert_eq!(counts[3], 0);
+        for (count, weight) in counts.iter().zip([10, 30, 60]) {
+            let expected = 10_000 * weight / 100;
+            assert!(count.abs_diff(expected) < 300, "{:?}", counts);
+        }
+    }
+
+    #[test]
+    fn test_zero_weights_select_uniformly() {
+        let records = [
+            srv(0, 0, "a.example.com"),
+            srv(0, 0, "b.example.com"),
+            srv(0, 0, "c.example.com"),
+        ];
+
+        for count in counts(&records, 9_000) {
+            assert!(count.abs_diff(3_000) < 300, "{}", count);
+        }
+        assert!(select_srv_target(&[], &mut XorShift::new(1)).is_none());
+    }
+
+    #[test]
+    fn test_srv_from_rdata() {
+        let rdata = [0, 10, 0, 5, 0x13, 0xC4, 3, b's', b'i', b'p', 0];
+        assert_eq!(
+            Srv::from_rdata(&rdata, &[]).unwrap(),
+            Srv {
+                priority: 10,
+                weight: 5,
+                port: 5060,
+                target: Name::parse("sip").unwrap(),
+            }
+        );
+        assert!(Srv::from_rdata(&rdata[..4], &[]).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+// src/synthesized.rs
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::dns::{Class, Name, ResourceRecord, Type};
+use crate::zone::AnswerSource;
+
+/// TTL of the synthesized records, the answer for a name never changes
+const SYNTHESIZED_TTL: u32 = 300;
+
+/// A zone without stored records that answers names with an IP address in their first label,
+/// like nip.io does. Under `example.com`:
+///
+/// ```text
+/// 10-0-0-5.example.com     A     10.0.0.5
+/// 2001-db8--1.example.com  AAAA  2001:db8::1
+/// ```
+///
+/// The dashes stand in for the dots or colons that can't be part of a label. Names that don't
+/// encode an address don't exist.
+pub struct SynthesizedZone {
+    domain: Name,
+}
+
+impl SynthesizedZone {
+    pub fn new(domain: Name) -> Self {
+        SynthesizedZone { domain }
+    }
+
+    // The address encoded in `name`, which has to be exactly one label below the domain
+    fn address(&self, name: &Name) -> Option<IpAddr> {
+        let (label, parent) = (name.labels().first()?, name.parent()?);
+        if !parent.eq_ignore_case(&self.domain) {
+            return None;
+        }
+
+        let label = std::str::from_utf8(label.as_bytes()).ok()?;
+        if label.contains("--") || label.matches('-').count() != 3 {
+            let address: Ipv6Addr = label.replace('-', ":").parse().ok()?;
+            return Some(IpAddr::V6(address));
+        }
+        let address: Ipv4Addr = label.replace('-', ".").parse().ok()?;
+        Some(IpAddr::V4(address))
+    }
+}
+
+impl AnswerSource for SynthesizedZone {
+    fn rrset(&self, name: &Name, qtype: &Type) -> Vec<ResourceRecord> {
+        let (rtype, rdata) = match (self.address(name), qtype) {
+            (Some(IpAddr::V4(address)), Type::A) => (Type::A, address.octets().to_vec()),
+            (Some(IpAddr::V6(address)), Type::AAAA) => (Type::AAAA, address.octets().to_vec()),
+            _ => return Vec::new(),
+        };
+
+        ResourceRecord::new(name.clone(), rtype, Class::IN, SYNTHESIZED_TTL, rdata)
+            .map(|record| vec![record])
+            .unwrap_or_default()
+    }
+
+    fn contains_name(&self, name: &Name) -> bool {
+        self.address(name).is_some() || name.eq_ignore_case(&self.domain)
+    }
+
+    fn apex(&self) -> Option<Name> {
+        Some(self.domain.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Message, RCODE_NAME_ERROR, RCODE_NO_ERROR, RCODE_REFUSED};
+
+    fn answer(name: &str, qtype: Type) -> Message {
+        let zone = SynthesizedZone::new(Name::parse("example.com").unwrap());
+        zone.answer(&Message::query(name, qtype).unwrap())
+    }
+
+    #[test]
+    fn test_synthesize_from_name() {
+        let response = answer("192-168-1-1.example.com", Type::A);
+        assert_eq!(response.header.rcode, RCODE_NO_ERROR);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(
+            response.answers[0].name.to_string(),
+            "192-168-1-1.example.com"
+        );
+        assert_eq!(response.answers[0].rtype, Type::A);
+        assert_eq!(response.answers[0].rdata, vec![192, 168, 1, 1]);
+
+        let response = answer("2001-DB8--1.Example.com", Type::AAAA);
+        let expected: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        assert_eq!(response.answers[0].rdata, expected.octets().to_vec());
+
+        // The name exists, just not with a record of the other address family
+        let response = answer("192-168-1-1.example.com", Type::AAAA);
+        assert_eq!(response.header.rcode, RCODE_NO_ERROR);
+        assert!(response.answers.is_empty());
+    }
+
+    #[test]
+    fn test_names_without_address_do_not_exist() {
+        for name in [
+            "300-1-1-1.example.com",
+            "192-168-1.example.com",
+            "www.example.com",
+            "www.192-168-1-1.example.com",
+        ] {
+            let response = answer(name, Type::A);
+            assert_eq!(response.header.rcode, RCODE_NAME_ERROR, "{}", name);
+            assert!(response.answers.is_empty());
+        }
+
+        // Outside the domain we don't know either way
+        let response = answer("192-168-1-1.example.org", Type::A);
+        assert_eq!(response.header.rcode, RCODE_REFUSED);
+        assert!(!response.header.aa);
+    }
+}
@@ -0,0 +1,182 @@
+// src/tcp.rs
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::dns::{ErrorCondition, Message};
+
+/// Connections that stay quiet for this long are closed (RFC 7766 section 6.2.3)
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Listen for DNS over TCP on `addr` and answer every query with `handler`.
+/// Each connection gets its own thread and may carry any number of queries.
+pub fn serve_tcp<A, H>(addr: A, handler: H) -> Result<(), ErrorCondition>
+where
+    A: ToSocketAddrs,
+    H: Fn(Message) -> Message + Send + Sync + 'static,
+{
+    serve_listener(TcpListener::bind(addr)?, handler)
+}
+
+// Like serve_tcp, on a listener that is already bound
+pub fn serve_listener<H>(listener: TcpListener, handler: H) -> Result<(), ErrorCondition>
+where
+    H: Fn(Message) -> Message + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept TCP connection: {}", e);
+                continue;
+            }
+        };
+
+        let handler = handler.clone();
+        std::thread::spawn(move || {
+            let peer = stream.peer_addr().ok();
+            if let Err(e) = handle_connection(stream, handler.as_ref()) {
+                eprintln!("TCP connection {:?} closed: {}", peer, e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn handle_connection<H>(mut stream: TcpStream, handler: &H) -> Result<(), ErrorCondition>
+where
+    H: Fn(Message) -> Message,
+{
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+
+    while let Some(query) = read_message(&mut stream)? {
+        let query = Message::from_bytes(&query)?;
+        println!(
+            "\nTCP query from {} (ID: {:#06x}, questions: {})",
+            stream.peer_addr()?,
+            query.header.id,
+            query.header.qdcount
+        );
+
+        let response = handler(query).to_bytes()?;
+        write_message(&mut stream, &response)?;
+    }
+
+    Ok(())
+}
+
+/// Read one length-prefixed message. TCP does not preserve message boundaries, so both the
+/// 2-byte length and the message may arrive split over several reads; `read_exact` keeps
+/// reading until it has them. Returns None when the peer closed the connection between messages.
+pub fn read_message<R: Read>(stream: &mut R) -> Result<Option<Vec<u8>>, ErrorCondition> {
+    let mut len = [0; 2];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+/// Write one message prefixed with its length as a 16 bit big-endian number
+pub fn write_message<W: Write>(stream: &mut W, message: &[u8]) -> Result<(), ErrorCondition> {
+    let len = u16::try_from(message.len()).map_err(|_| {
+        ErrorCondition::SerializationErr(format!(
+            "Message of {} bytes is too long for TCP",
+            message.len()
+        ))
+    })?;
+
+    // One write for length and message, so they don't go out as separate segments
+    let mut framed = Vec::with_capacity(2 + message.len());
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(message);
+    stream.write_all(&framed)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Class, ResourceRecord, Type};
+
+    // Hands out its data one byte per read call, like a slow TCP peer
+    struct Trickle(Vec<u8>, usize);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.1 == self.0.len() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[self.1];
+            self.1 += 1;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_read_message_across_partial_reads() {
+        let mut framed = Vec::new();
+        write_message(&mut framed, b"first").unwrap();
+        write_message(&mut framed, b"second message").unwrap();
+        assert_eq!(&framed[..2], &[0, 5]);
+
+        let mut stream = Trickle(framed, 0);
+        assert_eq!(read_message(&mut stream).unwrap().unwrap(), b"first");
+        assert_eq!(
+            read_message(&mut stream).unwrap().unwrap(),
+            b"second message"
+        );
+        assert!(read_message(&mut stream).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_truncated_message_is_an_error() {
+        let mut stream = Trickle(vec![0, 10, 1, 2, 3], 0);
+        assert!(read_message(&mut stream).is_err());
+    }
+
+    #[test]
+    fn test_serve_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            serve_listener(listener, |query: Message| {
+                let mut response = query.response();
+                let name = query.questions[0].name.clone();
+                response.answers.push(
+                    ResourceRecord::new(name, Type::A, Class::IN, 60, vec![10, 0, 0, 1]).unwrap(),
+                );
+                response
+            })
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let query = Message::query("www.example.com", Type::A).unwrap();
+        let bytes = query.to_bytes().unwrap();
+        for _ in 0..2 {
+            // Send the framed query in pieces to exercise reassembly on the server side
+            let mut framed = Vec::new();
+            write_message(&mut framed, &bytes).unwrap();
+            stream.write_all(&framed[..1]).unwrap();
+            stream.flush().unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            stream.write_all(&framed[1..10]).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            stream.write_all(&framed[10..]).unwrap();
+
+            let response = read_message(&mut stream).unwrap().unwrap();
+            let response = Message::from_bytes(&response).unwrap();
+            assert_eq!(response.header.id, query.header.id);
+            assert!(response.header.qr);
+            assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 1]);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+// src/throttle.rs
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::net::IpAddr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::bounded;
+
+/// Queries are counted per source in windows of this length
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Sources counted at most. Once that many are counted within a window, sources without a
+/// count are delayed, so a flood from spoofed addresses can't switch the throttle off.
+const MAX_TRACKED: usize = 10_000;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Slows down sources that send more than `threshold` queries per second by holding back
+/// their responses for `delay`. Unlike dropping, a legitimate client that happens to be busy
+/// still gets its answers, an abusive one just gets them slower.
+///
+/// Held back responses are sent from a timer thread, so a delayed client never keeps a worker
+/// busy and other clients don't notice it.
+pub struct Throttle {
+    threshold: u32,
+    delay: Duration,
+    counts: Mutex<HashMap<IpAddr, Window>>,
+    timer: Sender<Delayed>,
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    queries: u32,
+}
+
+// A job waiting in the timer thread, ordered so the earliest due comes out of the heap first
+struct Delayed {
+    due: Instant,
+    job: Job,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        self.due == other.due
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delayed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.due.cmp(&self.due)
+    }
+}
+
+impl Throttle {
+    pub fn new(threshold: u32, delay: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || run_timer(receiver));
+
+        Throttle {
+            threshold,
+            delay,
+            counts: Mutex::new(HashMap::new()),
+            timer: sender,
+        }
+    }
+
+    /// Count a query from `source`, returns how long to hold back its response if the source
+    /// is over the threshold
+    pub fn delay_for(&self, source: IpAddr) -> Option<Duration> {
+        let mut counts = self.counts.lock().unwrap();
+        let now = Instant::now();
+
+        let expired = |window: &Window| now.duration_since(window.start) >= WINDOW;
+        if !bounded::make_room(&mut counts, &source, MAX_TRACKED, expired) {
+            return Some(self.delay);
+        }
+
+        let window = counts.entry(source).or_insert(Window {
+            start: now,
+            queries: 0,
+        });
+        if now.duration_since(window.start) >= WINDOW {
+            window.start = now;
+            window.queries = 0;
+        }
+        window.queries += 1;
+
+        (window.queries > self.threshold).then_some(self.delay)
+    }
+
+    /// Run `job` once `delay` has passed, without blocking the caller
+    pub fn schedule<F>(&self, delay: Duration, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let delayed = Delayed {
+            due: Instant::now() + delay,
+            job: Box::new(job),
+        };
+        // The timer thread only stops once we are dropped
+        let _ = self.timer.send(delayed);
+    }
+}
+
+// Run scheduled jobs when they are due, until the throttle is dropped
+fn run_timer(receiver: Receiver<Delayed>) {
+    let mut pending = BinaryHeap::new();
+
+    loop {
+        let now = Instant::now();
+        while pending.peek().is_some_and(|next: &Delayed| next.due <= now) {
+            (pending.pop().unwrap().job)();
+        }
+
+        let received = match pending.peek() {
+            Some(next) => receiver.recv_timeout(next.due - now),
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(delayed) => pending.push(delayed),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_sources_over_threshold_are_delayed() {
+        let throttle = Throttle::new(2, Duration::from_millis(50));
+        let busy: IpAddr = "192.0.2.1".parse().unwrap();
+        let quiet: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert_eq!(throttle.delay_for(busy), None);
+        assert_eq!(throttle.delay_for(busy), None);
+        assert_eq!(throttle.delay_for(busy), Some(Duration::from_millis(50)));
+        assert_eq!(throttle.delay_for(quiet), None);
+    }
+
+    #[test]
+    fn test_untracked_sources_are_delayed_when_full() {
+        let throttle = Throttle::new(1, Duration::from_millis(50));
+        for n in 0..MAX_TRACKED as u32 {
+            assert_eq!(throttle.delay_for(IpAddr::from(n.to_be_bytes())), None);
+        }
+
+        let latecomer: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(
+            throttle.delay_for(latecomer),
+            Some(Duration::from_millis(50))
+        );
+    }
+
+    #[test]
+    fn test_scheduled_jobs_run_in_order_of_due_time() {
+        let throttle = Throttle::new(0, Duration::ZERO);
+        let (sender, receiver) = mpsc::channel();
+
+        for (delay, name) in [(150, "late"), (50, "early"), (100, "middle")] {
+            let sender = sender.clone();
+            throttle.schedule(Duration::from_millis(delay), move || {
+                sender.send(name).unwrap();
+            });
+        }
+
+        let order: Vec<_> = (0..3)
+            .map(|_| receiver.recv_timeout(Duration::from_secs(2)).unwrap())
+            .collect();
+        assert_eq!(order, ["early", "middle", "late"]);
+    }
+}
@@ -0,0 +1,107 @@
+// src/transport.rs
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+/// Where datagrams come from and go to, so the server loop doesn't depend on a real socket
+pub trait Transport: Send + Sync + 'static {
+    /// Wait for the next datagram and copy it into `buf`, returns its length and where it came
+    /// from. None means the transport is closed and nothing will arrive anymore.
+    fn recv(&self, buf: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>>;
+
+    fn send(&self, datagram: &[u8], to: SocketAddr) -> io::Result<()>;
+}
+
+/// The transport used in production, a bound UDP socket
+pub struct UdpTransport {
+    socket: UdpSocket,
+}
+
+impl UdpTransport {
+    pub fn new(socket: UdpSocket) -> Self {
+        UdpTransport { socket }
+    }
+}
+
+impl Transport for UdpTransport {
+    fn recv(&self, buf: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+        self.socket.recv_from(buf).map(Some)
+    }
+
+    fn send(&self, datagram: &[u8], to: SocketAddr) -> io::Result<()> {
+        self.socket.send_to(datagram, to).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+pub(crate) use channel::ChannelTransport;
+
+#[cfg(test)]
+mod channel {
+    use super::*;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    type Datagram = (Vec<u8>, SocketAddr);
+
+    /// A transport over in-memory channels, for running the whole server in tests without
+    /// binding sockets. The other end is a [`ChannelClient`], dropping it closes the transport.
+    pub(crate) struct ChannelTransport {
+        incoming: Mutex<Receiver<Datagram>>,
+        outgoing: Sender<Datagram>,
+    }
+
+    /// The clients' side of a [`ChannelTransport`]
+    pub(crate) struct ChannelClient {
+        to_server: Sender<Datagram>,
+        from_server: Receiver<Datagram>,
+    }
+
+    impl ChannelTransport {
+        pub(crate) fn pair() -> (ChannelTransport, ChannelClient) {
+            let (to_server, incoming) = mpsc::channel();
+            let (outgoing, from_server) = mpsc::channel();
+            let transport = ChannelTransport {
+                incoming: Mutex::new(incoming),
+                outgoing,
+            };
+            (
+                transport,
+                ChannelClient {
+                    to_server,
+                    from_server,
+                },
+            )
+        }
+    }
+
+    impl Transport for ChannelTransport {
+        fn recv(&self, buf: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+            let Ok((datagram, from)) = self.incoming.lock().unwrap().recv() else {
+                return Ok(None);
+            };
+            // Like UDP, whatever doesn't fit in the buffer is lost
+            let len = datagram.len().min(buf.len());
+            buf[..len].copy_from_slice(&datagram[..len]);
+            Ok(Some((len, from)))
+        }
+
+        fn send(&self, datagram: &[u8], to: SocketAddr) -> io::Result<()> {
+            self.outgoing
+                .send((datagram.to_vec(), to))
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client is gone"))
+        }
+    }
+
+    impl ChannelClient {
+        /// Deliver `datagram` to the server as if `from` sent it
+        pub(crate) fn send(&self, datagram: &[u8], from: SocketAddr) {
+            self.to_server.send((datagram.to_vec(), from)).unwrap();
+        }
+
+        /// The next datagram the server sent and who it was addressed to, None after `timeout`
+        pub(crate) fn recv(&self, timeout: Duration) -> Option<(Vec<u8>, SocketAddr)> {
+            self.from_server.recv_timeout(timeout).ok()
+        }
+    }
+}
@@ -0,0 +1,245 @@
+// src/udp.rs
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+
+use crate::dns::{ErrorCondition, Header};
+use crate::edns::MAX_EDNS_PAYLOAD;
+use crate::pool::ThreadPool;
+use crate::throttle::Throttle;
+use crate::transport::{Transport, UdpTransport};
+
+/// Listen for DNS over UDP on `addr` and answer every datagram with `handler`, running on the
+/// workers of `pool` so one slow query does not hold up the others. The handler gets the
+/// datagram and where it came from, returning None sends no reply. With a `throttle`, replies
+/// to sources sending too many queries are held back.
+pub fn serve_udp<A, H>(
+    addr: A,
+    pool: &ThreadPool,
+    throttle: Option<Throttle>,
+    handler: H,
+) -> Result<(), ErrorCondition>
+where
+    A: ToSocketAddrs,
+    H: Fn(&[u8], SocketAddr) -> Option<Vec<u8>> + Send + Sync + 'static,
+{
+    let transport = UdpTransport::new(UdpSocket::bind(addr)?);
+    serve_transport(transport, pool, throttle, handler)
+}
+
+/// Like serve_udp, over any transport. Returns once the transport is closed, which a UDP
+/// socket never is.
+pub fn serve_transport<T, H>(
+    transport: T,
+    pool: &ThreadPool,
+    throttle: Option<Throttle>,
+    handler: H,
+) -> Result<(), ErrorCondition>
+where
+    T: Transport,
+    H: Fn(&[u8], SocketAddr) -> Option<Vec<u8>> + Send + Sync + 'static,
+{
+    let transport = Arc::new(transport);
+    let handler = Arc::new(handler);
+    let throttle = throttle.map(Arc::new);
+    // Queries with EDNS can be as large as the payload we advertise, not just 512 bytes
+    let mut buf = [0; MAX_EDNS_PAYLOAD as usize];
+
+    loop {
+        let (len, addr) = match transport.recv(&mut buf) {
+            Ok(Some(v)) => v,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                eprintln!("Failed to receive: {}", e);
+                continue;
+            }
+        };
+
+        // The buffer is reused for the next datagram right away, so the job gets its own copy
+        let query = buf[..len].to_vec();
+        let transport = transport.clone();
+        let handler = handler.clone();
+        let throttle = throttle.clone();
+        let delay = throttle.as_ref().and_then(|t| t.delay_for(addr.ip()));
+        let queued = pool.execute(move || {
+            if let Ok(header) = Header::from_bytes(&query) {
+                println!(
+                    "\nQuery from {} (ID: {:#06x}, questions: {})",
+                    addr, header.id, header.qdcount
+                );
+            }
+
+            let Some(response) = handler(&query, addr) else {
+                return;
+            };
+            match (throttle, delay) {
+                (Some(throttle), Some(delay)) => {
+                    throttle.schedule(delay, move || send(&*transport, &response, addr))
+                }
+                _ => send(&*transport, &response, addr),
+            }
+        });
+        // Better to drop a query than to let a flood pile up unanswered
+        if !queued {
+            eprintln!("Dropped query from {}, all workers are busy", addr);
+        }
+    }
+}
+
+fn send(transport: &impl Transport, response: &[u8], addr: SocketAddr) {
+    if let Err(e) = transport.send(response, addr) {
+        eprintln!("Failed to send response to {}: {}", addr, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Message, Type, MAX_DNS_MESSAGE_SIZE};
+    use crate::edns::{Edns, EdnsOption};
+    use crate::server::Server;
+    use crate::transport::ChannelTransport;
+    use crate::zone::Zone;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_query_through_channel_transport() {
+        let zone = Zone::parse("www.example.com A 10.0.0.1 60").unwrap();
+        let server = Server::new(Some(Arc::new(zone)), Vec::new());
+        let (transport, client) = ChannelTransport::pair();
+        let serving = std::thread::spawn(move || {
+            let pool = ThreadPool::new(2);
+            serve_transport(transport, &pool, None, move |query: &[u8], source| {
+                server.handle(query, source)
+            })
+        });
+
+        let source: SocketAddr = "192.0.2.1:5353".parse().unwrap();
+        let query = Message::query("www.example.com", Type::A).unwrap();
+        client.send(&query.to_bytes().unwrap(), source);
+
+        let (response, to) = client.recv(Duration::from_secs(5)).unwrap();
+        assert_eq!(to, source);
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(response.header.id, query.header.id);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 1]);
+
+        // A datagram that isn't a query gets no answer at all
+        client.send(&[0; 4], source);
+        assert!(client.recv(Duration::from_millis(100)).is_none());
+
+        // Closing the client side ends the loop
+        drop(client);
+        serving.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_query_larger_than_512_bytes_is_answered() {
+        let zone = Zone::parse("www.example.com A 10.0.0.1 60").unwrap();
+        let server = Server::new(Some(Arc::new(zone)), Vec::new());
+        let (transport, client) = ChannelTransport::pair();
+        std::thread::spawn(move || {
+            let pool = ThreadPool::new(1);
+            serve_transport(transport, &pool, None, move |query: &[u8], source| {
+                server.handle(query, source)
+            })
+        });
+
+        // Padded (option 12, RFC 7830) well past the classic limit
+        let mut query = Message::query("www.example.com", Type::A).unwrap();
+        let mut edns = Edns::new(MAX_EDNS_PAYLOAD);
+        edns.options.push(EdnsOption::Unknown {
+            code: 12,
+            data: vec![0; 600],
+        });
+        query.edns = Some(edns);
+        let query = query.to_bytes().unwrap();
+        assert!(query.len() > MAX_DNS_MESSAGE_SIZE);
+
+        let source: SocketAddr = "192.0.2.1:5353".parse().unwrap();
+        client.send(&query, source);
+        let (response, _) = client.recv(Duration::from_secs(5)).unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_parallel_queries() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let pool = ThreadPool::new(8);
+            serve_transport(UdpTransport::new(socket), &pool, None, |query: &[u8], _| {
+                // A slow answer, like one that has to be forwarded
+                std::thread::sleep(Duration::from_millis(100));
+                let query = Message::from_bytes(query).ok()?;
+                query.response().to_bytes().ok()
+            })
+        });
+
+        let started = Instant::now();
+        let clients: Vec<_> = (0..32)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    let query = Message::query("www.example.com", Type::A).unwrap();
+                    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+                    client
+                        .set_read_timeout(Some(Duration::from_secs(5)))
+                        .unwrap();
+                    client.send_to(&query.to_bytes().unwrap(), addr).unwrap();
+
+                    let mut buf = [0; MAX_EDNS_PAYLOAD as usize];
+                    let len = client.recv(&mut buf).unwrap();
+                    let response = Header::from_bytes(&buf[..len]).unwrap();
+                    assert!(response.qr);
+                    assert_eq!(response.id, query.header.id);
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+
+        // 32 queries of 100ms each on 8 workers, done one at a time they'd take 3.2s
+        assert!(started.elapsed() < Duration::from_millis(2000));
+    }
+
+    #[test]
+    fn test_flagged_source_is_delayed_without_holding_up_others() {
+        let (transport, client) = ChannelTransport::pair();
+        std::thread::spawn(move || {
+            // A single worker: if it slept through the delay, nobody else would be answered
+            let pool = ThreadPool::new(1);
+            let throttle = Throttle::new(1, Duration::from_millis(500));
+            serve_transport(transport, &pool, Some(throttle), |query: &[u8], _| {
+                let query = Message::from_bytes(query).ok()?;
+                query.response().to_bytes().ok()
+            })
+        });
+
+        let flagged: SocketAddr = "192.0.2.2:5353".parse().unwrap();
+        let other: SocketAddr = "192.0.2.1:5353".parse().unwrap();
+        let query = Message::query("www.example.com", Type::A).unwrap();
+        let query = query.to_bytes().unwrap();
+        // Send the query from `source` and wait for the answer, returns how long it took
+        let time_query = |source| {
+            let started = Instant::now();
+            client.send(&query, source);
+            let (_, to) = client.recv(Duration::from_secs(5)).unwrap();
+            assert_eq!(to, source);
+            started.elapsed()
+        };
+
+        // The first query is within the threshold of one per second, the second is not
+        assert!(time_query(flagged) < Duration::from_millis(250));
+        let started = Instant::now();
+        client.send(&query, flagged);
+
+        assert!(time_query(other) < Duration::from_millis(250));
+
+        let (_, to) = client.recv(Duration::from_secs(5)).unwrap();
+        assert_eq!(to, flagged);
+        assert!(started.elapsed() >= Duration::from_millis(500));
+    }
+}
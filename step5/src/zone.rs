@@ -0,0 +1,708 @@
+// src/zone.rs
+use std::collections::{HashMap, HashSet};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+use crate::dns::{
+    Class, ErrorCondition, Message, Name, ResourceRecord, Type, RCODE_NAME_ERROR,
+    RCODE_NOT_IMPLEMENTED, RCODE_REFUSED,
+};
+
+/// Records loaded from a zone file, one record per line:
+///
+/// ```text
+/// # name            type  value              ttl
+/// www.example.com   A     172.67.221.148     60
+/// example.com       AAAA  2606:4700::1       300
+/// sub.example.com   NS    ns1.sub.example.com  3600
+/// ```
+///
+/// Blank lines and lines starting with `#` or `;` are ignored. A `$ORIGIN example.com` line
+/// before the records sets the apex of the zone, every owner name has to be at or below it.
+/// Without one, the apex is the longest name all owner names end with, and a file with names
+/// that have nothing but the root in common is rejected. NS records below the apex delegate
+/// to another zone.
+#[derive(Debug, Default)]
+pub struct Zone {
+    // Keyed by the canonical form of the owner name
+    records: HashMap<(Vec<u8>, Type), Vec<ResourceRecord>>,
+    // Owner names and all their ancestors, the ones at or below the apex exist in the zone
+    names: HashSet<Vec<u8>>,
+    apex: Option<Name>,
+    // Set by $ORIGIN rather than taken from the owner names
+    explicit_apex: bool,
+}
+
+/// The outcome of looking up a name and type in a zone
+#[derive(Debug, Clone)]
+pub enum LookupResult {
+    /// Records of the queried type, possibly synthesized from a wildcard
+    Found(Vec<ResourceRecord>),
+    /// The name exists, but has no records of the queried type
+    NoData,
+    /// The name does not exist
+    NxDomain,
+    /// The name is at or below a zone cut, these are the NS records of the child zone
+    Delegation(Vec<ResourceRecord>),
+}
+
+impl Zone {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Zone, ErrorCondition> {
+        let text = std::fs::read_to_string(path)?;
+        Zone::parse(&text)
+    }
+
+    /// An empty zone with `apex` as its top, whatever names are inserted later
+    pub fn with_apex(apex: Name) -> Zone {
+        Zone {
+            apex: Some(apex),
+            explicit_apex: true,
+            ..Zone::default()
+        }
+    }
+
+    // Parse the zone file contents, every malformed line is reported with its line number
+    pub fn parse(text: &str) -> Result<Zone, ErrorCondition> {
+        let mut zone = Zone::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            zone.parse_line(line)
+                .map_err(|reason| ErrorCondition::ZoneErr {
+                    line: i + 1,
+                    reason,
+                })?;
+        }
+
+        Ok(zone)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        if let Some(origin) = line.strip_prefix("$ORIGIN") {
+            if !self.records.is_empty() || self.explicit_apex {
+                return Err("$ORIGIN has to come once, before the records".to_string());
+            }
+            let origin = origin.trim();
+            let apex = Name::parse(&normalize_name(origin))
+                .map_err(|e| format!("invalid origin '{}': {}", origin, e))?;
+            *self = Zone::with_apex(apex);
+            return Ok(());
+        }
+
+        let record = parse_record(line)?;
+        if let Some(apex) = self.apex.as_ref().filter(|_| self.explicit_apex) {
+            if !record.name.ends_with(apex) {
+                return Err(format!("'{}' is outside the zone {}", record.name, apex));
+            }
+        }
+        let name = record.name.to_string();
+        self.insert(record);
+
+        // One file is one zone, a guessed apex above all of them would make their NS records
+        // delegations
+        if self.apex.as_ref().is_some_and(Name::is_root) {
+            return Err(format!(
+                "'{}' has no parent in common with the names before it, set the apex with $ORIGIN",
+                name
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, record: ResourceRecord) {
+        if !self.explicit_apex {
+            let apex = match self.apex.take() {
+                Some(apex) => common_suffix(&apex, &record.name),
+                None => record.name.clone(),
+            };
+            self.apex = Some(apex);
+        }
+
+        // The apex is only known once all records are in, so ancestors are kept up to the root
+        let mut ancestor = record.name.parent();
+        while let Some(name) = ancestor {
+            self.names.insert(name.to_canonical_bytes());
+            ancestor = name.parent();
+        }
+
+        let name = record.name.to_canonical_bytes();
+        self.names.insert(name.clone());
+        self.records
+            .entry((name, record.rtype.clone()))
+            .or_default()
+            .push(record);
+    }
+
+    // All records in the zone, in no particular order
+    pub fn records(&self) -> impl Iterator<Item = &ResourceRecord> {
+        self.records.values().flatten()
+    }
+
+    // Names without records of their own that exist because names below them do, between an
+    // owner name and the apex (RFC 4592 section 2.2.2), in canonical form
+    pub(crate) fn empty_non_terminals(&self) -> HashSet<Vec<u8>> {
+        let Some(apex) = &self.apex else {
+            return HashSet::new();
+        };
+        let owners: HashSet<&[u8]> = self.records.keys().map(|(name, _)| &name[..]).collect();
+
+        let mut empty = HashSet::new();
+        for record in self.records() {
+            let mut ancestor = record.name.parent().filter(|name| name.ends_with(apex));
+            while let Some(name) = ancestor {
+                let key = name.to_canonical_bytes();
+                if !owners.contains(&key[..]) {
+                    empty.insert(key);
+                }
+                ancestor = name.parent().filter(|name| name.ends_with(apex));
+            }
+        }
+        empty
+    }
+}
+
+/// Anything the server can answer queries from, such as a [`Zone`] loaded into memory or a
+/// memory-mapped [`MmapZone`](crate::mmap_zone::MmapZone).
+pub trait AnswerSource {
+    // The records of one type stored at exactly this name, empty if there are none
+    fn rrset(&self, name: &Name, qtype: &Type) -> Vec<ResourceRecord>;
+
+    // Whether the name exists: it has records of any type, or names below it have
+    fn contains_name(&self, name: &Name) -> bool;
+
+    // The top of the zone, None for a zone without records
+    fn apex(&self) -> Option<Name>;
+
+    /// The closest encloser of `name` (RFC 4592 section 3.3.1): the longest of the name itself
+    /// and its ancestors that exists in the zone. The apex always counts as existing, so for a
+    /// name in the zone this is the apex or a name below it. Names outside the zone get the root.
+    fn closest_encloser(&self, name: &Name) -> Name {
+        let apex = self.apex().unwrap_or_default();
+        let mut current = name.clone();
+        while !current.eq_ignore_case(&apex) && !self.contains_name(&current) {
+            match current.parent() {
+                Some(parent) => current = parent,
+                None => break,
+            }
+        }
+        current
+    }
+
+    /// Look up `qtype` records for `name`, following delegations and wildcards (RFC 1034
+    /// section 4.3.2)
+    fn lookup(&self, name: &Name, qtype: &Type) -> LookupResult {
+        if let Some(referral) = find_delegation(self, name) {
+            return LookupResult::Delegation(referral);
+        }
+
+        if self.contains_name(name) {
+            return found_or_nodata(self.rrset(name, qtype));
+        }
+
+        // Synthesize the answer from a covering wildcard, owned by the queried name
+        let Some(wildcard) = find_wildcard(self, name) else {
+            return LookupResult::NxDomain;
+        };
+        let owner = Name::substitute_wildcard(&wildcard, name);
+        let records: Vec<ResourceRecord> = self
+            .rrset(&wildcard, qtype)
+            .into_iter()
+            .map(|record| ResourceRecord {
+                name: owner.clone(),
+                ..record
+            })
+            .collect();
+        found_or_nodata(records)
+    }
+
+    // Build the response to a query, names in the zone without any record get NXDOMAIN. Names
+    // outside of it are refused, we have no say about whether they exist.
+    fn answer(&self, query: &Message) -> Message {
+        let mut response = query.response();
+        response.header.aa = true;
+
+        if query.header.opcode != 0 {
+            response.header.rcode = RCODE_NOT_IMPLEMENTED;
+            return response;
+        }
+
+        let apex = self.apex();
+        let in_zone = |name: &Name| apex.as_ref().is_some_and(|apex| name.ends_with(apex));
+        if !query
+            .questions
+            .iter()
+            .all(|question| in_zone(&question.name))
+        {
+            response.header.aa = false;
+            response.header.rcode = RCODE_REFUSED;
+            return response;
+        }
+
+        for question in &query.questions {
+            match self.lookup(&question.name, &question.qtype) {
+                LookupResult::Found(records) => response.answers.extend(records),
+                LookupResult::NoData => {}
+                LookupResult::NxDomain => response.header.rcode = RCODE_NAME_ERROR,
+                // A referral: the child zone is authoritative, not us
+                LookupResult::Delegation(servers) => {
+                    response.header.aa = false;
+                    response.additionals.extend(glue(self, &servers));
+                    response.authorities.extend(servers);
+                }
+            }
+        }
+
+        response
+    }
+}
+
+fn found_or_nodata(records: Vec<ResourceRecord>) -> LookupResult {
+    if records.is_empty() {
+        LookupResult::NoData
+    } else {
+        LookupResult::Found(records)
+    }
+}
+
+// The NS records of the zone cut at or above `name`, if it lies in a delegated part of the zone.
+// NS records at the apex describe the zone itself and are no delegation. With nested cuts the
+// one closest to the apex wins, everything below it belongs to the child zone.
+fn find_delegation<S: AnswerSource + ?Sized>(
+    source: &S,
+    name: &Name,
+) -> Option<Vec<ResourceRecord>> {
+    let apex = source.apex()?;
+    if !name.ends_with(&apex) {
+        return None;
+    }
+
+    let mut below_apex = Vec::new();
+    let mut current = Some(name.clone());
+    while let Some(candidate) = current.filter(|n| n.labels().len() > apex.labels().len()) {
+        current = candidate.parent();
+        below_apex.push(candidate);
+    }
+
+    below_apex.iter().rev().find_map(|cut| {
+        let servers = source.rrset(cut, &Type::NS);
+        (!servers.is_empty()).then_some(servers)
+    })
+}
+
+// The addresses of the name servers in a referral that the zone has records for, which the
+// resolver needs when the servers are inside the delegated zone (RFC 1034 section 4.2.1)
+fn glue<S: AnswerSource + ?Sized>(source: &S, servers: &[ResourceRecord]) -> Vec<ResourceRecord> {
+    let mut glue = Vec::new();
+    for server in servers {
+        let Ok((target, _)) = Name::from_message(&server.rdata, 0) else {
+            continue;
+        };
+        for record in [Type::A, Type::AAAA]
+            .iter()
+            .flat_map(|qtype| source.rrset(&target, qtype))
+        {
+            if !glue.contains(&record) {
+                glue.push(record);
+            }
+        }
+    }
+    glue
+}
+
+// The wildcard `*.<ancestor>` covering a name that does not exist. Only ancestors up to the
+// closest encloser count, a wildcard further up does not reach past an existing name.
+fn find_wildcard<S: AnswerSource + ?Sized>(source: &S, name: &Name) -> Option<Name> {
+    let encloser = source.closest_encloser(name);
+    let mut ancestor = name.parent();
+    while let Some(current) = ancestor {
+        let wildcard = current.wildcard_child();
+        if source.contains_name(&wildcard) {
+            return Some(wildcard);
+        }
+        if current.eq_ignore_case(&encloser) {
+            return None;
+        }
+        ancestor = current.parent();
+    }
+    None
+}
+
+impl AnswerSource for Zone {
+    fn rrset(&self, name: &Name, qtype: &Type) -> Vec<ResourceRecord> {
+        self.records
+            .get(&(name.to_canonical_bytes(), qtype.clone()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn contains_name(&self, name: &Name) -> bool {
+        self.apex.as_ref().is_some_and(|apex| name.ends_with(apex))
+            && self.names.contains(&name.to_canonical_bytes())
+    }
+
+    fn apex(&self) -> Option<Name> {
+        self.apex.clone()
+    }
+}
+
+// The longest name both `a` and `b` end with, the root if they have nothing in common
+fn common_suffix(a: &Name, b: &Name) -> Name {
+    let mut suffix = a.clone();
+    while !b.ends_with(&suffix) {
+        suffix = suffix.parent().unwrap_or_default();
+    }
+    suffix
+}
+
+// Names in zone files are case-insensitive and may be written fully qualified with a trailing dot
+fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn parse_record(line: &str) -> Result<ResourceRecord, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [name, rtype, value, ttl] = fields[..] else {
+        return Err(format!(
+            "expected 'name type value ttl', found {} field(s)",
+            fields.len()
+        ));
+    };
+
+    let name = match Name::parse(&normalize_name(name)) {
+        Ok(parsed) if !parsed.is_root() => parsed,
+        Ok(_) => return Err(format!("invalid name '{}'", name)),
+        Err(e) => return Err(format!("invalid name '{}': {}", name, e)),
+    };
+
+    let ttl: u32 = ttl.parse().map_err(|_| format!("invalid TTL '{}'", ttl))?;
+
+    let (rtype, rdata) = match rtype.to_ascii_uppercase().as_str() {
+        "A" => {
+            let addr: Ipv4Addr = value
+                .parse()
+                .map_err(|_| format!("invalid IPv4 address '{}'", value))?;
+            (Type::A, addr.octets().to_vec())
+        }
+        "AAAA" => {
+            let addr: Ipv6Addr = value
+                .parse()
+                .map_err(|_| format!("invalid IPv6 address '{}'", value))?;
+            (Type::AAAA, addr.octets().to_vec())
+        }
+        "NS" => {
+            let server = Name::parse(&normalize_name(value))
+                .and_then(|server| server.to_bytes())
+                .map_err(|e| format!("invalid name server '{}': {}", value, e))?;
+            (Type::NS, server)
+        }
+        other => return Err(format!("unsupported record type '{}'", other)),
+    };
+
+    ResourceRecord::new(name, rtype, Class::IN, ttl, rdata).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZONE: &str = "
+        # A small test zone
+        www.example.com  A     172.67.221.148  60
+        www.example.com  A     172.67.221.149  60
+        example.com      AAAA  2606:4700::1    300
+    ";
+
+    fn query(name: &str, qtype: Type) -> Message {
+        Message::query(name, qtype).unwrap()
+    }
+
+    #[test]
+    fn test_lookup_by_name_and_type() {
+        let zone = Zone::parse(ZONE).unwrap();
+
+        let name = |n: &str| Name::parse(n).unwrap();
+        let a = zone.rrset(&name("WWW.example.com."), &Type::A);
+        assert_eq!(a.len(), 2);
+        assert_eq!(a[0].rdata, vec![172, 67, 221, 148]);
+
+        let aaaa = zone.rrset(&name("example.com"), &Type::AAAA);
+        assert_eq!(aaaa.len(), 1);
+        assert_eq!(aaaa[0].ttl, 300);
+        assert_eq!(aaaa[0].rdlength, 16);
+
+        assert!(zone.rrset(&name("example.com"), &Type::A).is_empty());
+    }
+
+    #[test]
+    fn test_names_are_not_limited_to_ascii() {
+        let zone = Zone::parse("Café.example.com  A  10.0.0.1  60").unwrap();
+        let name = Name::parse("CAFé.example.com").unwrap();
+
+        assert!(zone.contains_name(&name));
+        let records = zone.rrset(&name, &Type::A);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].name, Name::parse("café.example.com").unwrap());
+
+        // Only ASCII letters compare case-insensitively, É is another name than é
+        assert!(!zone.contains_name(&Name::parse("cafÉ.example.com").unwrap()));
+    }
+
+    #[test]
+    fn test_answer_from_wildcard() {
+        let zone = Zone::parse(
+            "
+            *.example.com    A  10.0.0.1  60
+            www.example.com  A  10.0.0.2  60
+            ",
+        )
+        .unwrap();
+
+        let response = zone.answer(&query("foo.example.com", Type::A));
+        assert_eq!(response.header.rcode, 0);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].name.to_string(), "foo.example.com");
+        assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 1]);
+
+        // An existing name is answered by its own records
+        let response = zone.answer(&query("www.example.com", Type::A));
+        assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 2]);
+
+        // The wildcard does not reach below an existing name
+        let response = zone.answer(&query("foo.www.example.com", Type::A));
+        assert_eq!(response.header.rcode, RCODE_NAME_ERROR);
+    }
+
+    #[test]
+    fn test_lookup_nodata_and_delegation() {
+        let zone = Zone::parse(
+            "
+            example.com      NS  ns1.example.com      3600
+            ns1.example.com  A   10.0.0.53            3600
+            www.example.com  A   10.0.0.1             60
+            sub.example.com  NS  ns1.sub.example.com  3600
+            sub.example.com  NS  ns.elsewhere.net     3600
+            ns1.sub.example.com  A  10.0.1.53         3600
+            ",
+        )
+        .unwrap();
+        let name = |n: &str| Name::parse(n).unwrap();
+        assert_eq!(zone.apex(), Some(name("example.com")));
+
+        let found = zone.lookup(&name("www.example.com"), &Type::A);
+        assert!(matches!(found, LookupResult::Found(records) if records.len() == 1));
+        let found = zone.lookup(&name("example.com"), &Type::NS);
+        assert!(matches!(found, LookupResult::Found(records) if records.len() == 1));
+
+        let missing_type = zone.lookup(&name("www.example.com"), &Type::AAAA);
+        assert!(matches!(missing_type, LookupResult::NoData));
+        let missing_name = zone.lookup(&name("ftp.example.com"), &Type::A);
+        assert!(matches!(missing_name, LookupResult::NxDomain));
+
+        for below_cut in ["sub.example.com", "www.sub.example.com"] {
+            let referral = zone.lookup(&name(below_cut), &Type::A);
+            assert!(
+                matches!(&referral, LookupResult::Delegation(servers) if servers.len() == 2),
+                "{}",
+                below_cut
+            );
+        }
+
+        // NODATA is a successful answer without records, the referral has no answer either
+        let response = zone.answer(&query("www.example.com", Type::AAAA));
+        assert_eq!(response.header.rcode, 0);
+        assert!(response.header.aa);
+        assert!(response.answers.is_empty());
+
+        let response = zone.answer(&query("www.sub.example.com", Type::A));
+        assert_eq!(response.header.rcode, 0);
+        assert!(!response.header.aa);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities[0].name, name("sub.example.com"));
+
+        // The server inside the child zone comes with its address, the other one can be
+        // looked up elsewhere
+        assert_eq!(response.additionals.len(), 1);
+        assert_eq!(response.additionals[0].name, name("ns1.sub.example.com"));
+        assert_eq!(response.additionals[0].rdata, vec![10, 0, 1, 53]);
+    }
+
+    #[test]
+    fn test_one_zone_per_file() {
+        let two_zones = "
+            example.com      NS  ns1.example.com  3600
+            www.example.com  A   10.0.0.1         60
+            example.org      A   10.0.0.2         60
+            ";
+        let err = Zone::parse(two_zones).unwrap_err();
+        assert!(matches!(err, ErrorCondition::ZoneErr { line: 4, .. }));
+
+        // With the apex given, names outside of it are an error too
+        let err = Zone::parse(&format!("$ORIGIN example.com{}", two_zones)).unwrap_err();
+        assert!(matches!(err, ErrorCondition::ZoneErr { line: 4, .. }));
+        assert!(Zone::parse("www.example.com A 10.0.0.1 60\n$ORIGIN example.com").is_err());
+
+        // The NS records at the apex describe the zone itself, they are no delegation
+        let zone = Zone::parse(
+            "
+            $ORIGIN example.com.
+            example.com      NS  ns1.example.com  3600
+            www.example.com  A   10.0.0.1         60
+            ",
+        )
+        .unwrap();
+        let name = |n: &str| Name::parse(n).unwrap();
+        assert_eq!(zone.apex(), Some(name("example.com")));
+
+        let response = zone.answer(&query("www.example.com", Type::A));
+        assert!(response.header.aa);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].rdata, vec![10, 0, 0, 1]);
+
+        // The apex holds even when the records would suggest another one
+        assert!(zone.contains_name(&name("example.com")));
+        let response = zone.answer(&query("ftp.example.com", Type::A));
+        assert_eq!(response.header.rcode, RCODE_NAME_ERROR);
+    }
+
+    #[test]
+    fn test_names_outside_the_zone_are_refused() {
+        let zone = Zone::parse(ZONE).unwrap();
+
+        for name in ["google.com", "com", "example.org"] {
+            let response = zone.answer(&query(name, Type::A));
+            assert_eq!(response.header.rcode, RCODE_REFUSED, "{}", name);
+            assert!(!response.header.aa, "{}", name);
+            assert!(response.answers.is_empty(), "{}", name);
+        }
+
+        // Without records there is no zone to answer from at all
+        let response = Zone::default().answer(&query("www.example.com", Type::A));
+        assert_eq!(response.header.rcode, RCODE_REFUSED);
+    }
+
+    #[test]
+    fn test_empty_non_terminal_exists() {
+        let zone = Zone::parse(
+            "
+            example.com        A  10.0.0.1  60
+            a.b.example.com    A  10.0.0.2  60
+            ",
+        )
+        .unwrap();
+        let name = |n: &str| Name::parse(n).unwrap();
+
+        assert!(zone.contains_name(&name("B.example.com")));
+        let empty = zone.lookup(&name("b.example.com"), &Type::A);
+        assert!(matches!(empty, LookupResult::NoData));
+        assert_eq!(
+            zone.empty_non_terminals(),
+            HashSet::from([name("b.example.com").to_canonical_bytes()])
+        );
+
+        // A wildcard next to it does not cover the empty non-terminal, the name is there
+        let mut zone = zone;
+        zone.insert(
+            ResourceRecord::new(
+                name("*.example.com"),
+                Type::A,
+                Class::IN,
+                60,
+                vec![10, 0, 0, 3],
+            )
+            .unwrap(),
+        );
+        let empty = zone.lookup(&name("b.example.com"), &Type::A);
+        assert!(matches!(empty, LookupResult::NoData));
+        let synthesized = zone.lookup(&name("c.example.com"), &Type::A);
+        assert!(matches!(synthesized, LookupResult::Found(_)));
+    }
+
+    #[test]
+    fn test_closest_encloser() {
+        let zone = Zone::parse(
+            "
+            example.com    A  10.0.0.1  60
+            b.example.com  A  10.0.0.2  60
+            ",
+        )
+        .unwrap();
+        let name = |n: &str| Name::parse(n).unwrap();
+
+        assert_eq!(
+            zone.closest_encloser(&name("a.b.example.com")),
+            name("b.example.com")
+        );
+        assert_eq!(
+            zone.closest_encloser(&name("b.example.com")),
+            name("b.example.com")
+        );
+        assert_eq!(
+            zone.closest_encloser(&name("x.y.example.com")),
+            name("example.com")
+        );
+        assert!(zone.closest_encloser(&name("example.org")).is_root());
+
+        // An empty non-terminal is as much an encloser as a name with records, a wildcard
+        // further up does not reach past it
+        let zone = Zone::parse(
+            "
+            example.com      A  10.0.0.1  60
+            a.b.example.com  A  10.0.0.2  60
+            *.example.com    A  10.0.0.3  60
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            zone.closest_encloser(&name("x.b.example.com")),
+            name("b.example.com")
+        );
+        let missing = zone.lookup(&name("x.b.example.com"), &Type::A);
+        assert!(matches!(missing, LookupResult::NxDomain));
+
+        // The apex encloses its names even when it has no records of its own
+        let zone = Zone::parse(
+            "
+            www.example.com   A  10.0.0.1  60
+            mail.example.com  A  10.0.0.2  60
+            ",
+        )
+        .unwrap();
+        assert!(zone.rrset(&name("example.com"), &Type::A).is_empty());
+        assert!(zone.contains_name(&name("example.com")));
+        assert!(!zone.contains_name(&name("com")));
+        let encloser = zone.closest_encloser(&name("a.b.EXAMPLE.com"));
+        assert!(encloser.eq_ignore_case(&name("example.com")));
+    }
+
+    #[test]
+    fn test_malformed_line_reports_line_number() {
+        let err =
+            Zone::parse("www.example.com A 1.2.3.4 60\nexample.com A not-an-ip 60").unwrap_err();
+        assert!(matches!(err, ErrorCondition::ZoneErr { line: 2, .. }));
+
+        assert!(Zone::parse("www.example.com A 1.2.3.4").is_err());
+        assert!(Zone::parse("www.example.com MX 1.2.3.4 60").is_err());
+        assert!(Zone::parse("sub.example.com NS ns..example.com 60").is_err());
+        assert!(Zone::parse("www.example.com A 1.2.3.4 soon").is_err());
+    }
+
+    #[test]
+    fn test_answer_nxdomain_for_unknown_name() {
+        let zone = Zone::parse(ZONE).unwrap();
+
+        let response = zone.answer(&query("www.example.com", Type::A));
+        assert_eq!(response.header.rcode, 0);
+        assert_eq!(response.answers.len(), 2);
+
+        let query = query("missing.example.com", Type::A);
+        let response = zone.answer(&query);
+        assert_eq!(response.header.rcode, RCODE_NAME_ERROR);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.header.id, query.header.id);
+        assert!(response.header.qr);
+    }
+}